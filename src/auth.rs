@@ -0,0 +1,191 @@
+// auth.rs
+use crate::config::{AuthConfig, Secret};
+use reqwest::header::{HeaderName, HeaderValue};
+use reqwest::RequestBuilder;
+use std::error::Error;
+use std::path::Path;
+
+// Looks up an environment variable, the tests pass their own environment instead of changing the global one
+type Env<'a> = &'a dyn Fn(&str) -> Result<String, std::env::VarError>;
+
+impl Secret {
+    /// Read the secret, without the trailing newline files and credentials usually end with
+    pub fn resolve(&self) -> Result<String, Box<dyn Error>> {
+        self.resolve_with_env(&|name| std::env::var(name))
+    }
+
+    fn resolve_with_env(&self, env: Env) -> Result<String, Box<dyn Error>> {
+        let secret = match self {
+            Secret::File(path) => std::fs::read_to_string(path)
+                .map_err(|e| format!("failed to read secret file {} - {}", path, e))?,
+            Secret::Env(name) => {
+                env(name).map_err(|e| format!("failed to read secret from ${} - {}", name, e))?
+            }
+            Secret::Credential(name) => {
+                let credentials_directory = env("CREDENTIALS_DIRECTORY").map_err(|_| {
+                    format!(
+                        "failed to read credential {}, $CREDENTIALS_DIRECTORY is not set",
+                        name
+                    )
+                })?;
+                let path = Path::new(&credentials_directory).join(name);
+                std::fs::read_to_string(&path)
+                    .map_err(|e| format!("failed to read credential {} - {}", path.display(), e))?
+            }
+        };
+        Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+/// Add the credentials of the auth config to the request.
+/// Secrets are resolved on every request, so rotated secrets are picked up without a restart.
+///
+/// # Arguments
+/// * `request` - The request to add the credentials to
+/// * `auth` - The auth config of the external source
+///
+/// # Returns
+/// * `Result<RequestBuilder, Box<dyn Error>>` - The request with credentials, or an error when a secret can't be read
+///
+pub fn apply(request: RequestBuilder, auth: &AuthConfig) -> Result<RequestBuilder, Box<dyn Error>> {
+    apply_with_env(request, auth, &|name| std::env::var(name))
+}
+
+fn apply_with_env(
+    request: RequestBuilder,
+    auth: &AuthConfig,
+    env: Env,
+) -> Result<RequestBuilder, Box<dyn Error>> {
+    if auth.bearer_token.is_some() && auth.basic.is_some() {
+        return Err("only one of bearer_token and basic auth can be configured".into());
+    }
+
+    let mut request = request;
+    if let Some(bearer_token) = &auth.bearer_token {
+        request = request.bearer_auth(bearer_token.resolve_with_env(env)?);
+    }
+    if let Some(basic) = &auth.basic {
+        request = request.basic_auth(&basic.username, Some(basic.password.resolve_with_env(env)?));
+    }
+    for (name, secret) in &auth.headers {
+        let header_name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|e| format!("invalid header name {} - {}", name, e))?;
+        let mut header_value = HeaderValue::from_str(&secret.resolve_with_env(env)?)
+            .map_err(|e| format!("invalid value for header {} - {}", name, e))?;
+        header_value.set_sensitive(true);
+        request = request.header(header_name, header_value);
+    }
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BasicAuth;
+    use reqwest::header::AUTHORIZATION;
+    use std::collections::BTreeMap;
+
+    fn build(auth: &AuthConfig) -> reqwest::Request {
+        build_with_env(auth, &[])
+    }
+
+    // Build the request with only the given environment variables
+    fn build_with_env(auth: &AuthConfig, vars: &[(&str, &str)]) -> reqwest::Request {
+        let env = |name: &str| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+                .ok_or(std::env::VarError::NotPresent)
+        };
+        let request = reqwest::Client::new().get("https://idec.example.com/export");
+        apply_with_env(request, auth, &env)
+            .unwrap()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_bearer_token_from_file() {
+        let token_file = "testdata/test_bearer_token_from_file";
+        std::fs::write(token_file, "my-token\n").unwrap();
+        let auth = AuthConfig {
+            bearer_token: Some(Secret::File(token_file.to_string())),
+            ..Default::default()
+        };
+
+        let request = build(&auth);
+        assert_eq!(request.headers()[AUTHORIZATION], "Bearer my-token");
+
+        // Clean up
+        std::fs::remove_file(token_file).unwrap();
+    }
+
+    #[test]
+    fn test_basic_auth_and_headers_from_env() {
+        let auth = AuthConfig {
+            basic: Some(BasicAuth {
+                username: "coredns".to_string(),
+                password: Secret::Env("TEST_BASIC_AUTH_PASSWORD".to_string()),
+            }),
+            headers: BTreeMap::from([(
+                "X-Api-Key".to_string(),
+                Secret::Env("TEST_BASIC_AUTH_API_KEY".to_string()),
+            )]),
+            ..Default::default()
+        };
+
+        let request = build_with_env(
+            &auth,
+            &[
+                ("TEST_BASIC_AUTH_PASSWORD", "secret"),
+                ("TEST_BASIC_AUTH_API_KEY", "key"),
+            ],
+        );
+        // base64 of coredns:secret
+        assert_eq!(
+            request.headers()[AUTHORIZATION],
+            "Basic Y29yZWRuczpzZWNyZXQ="
+        );
+        assert_eq!(request.headers()["X-Api-Key"], "key");
+    }
+
+    #[test]
+    fn test_bearer_token_from_systemd_credential() {
+        std::fs::create_dir_all("testdata/credentials").unwrap();
+        std::fs::write("testdata/credentials/idec-token", "credential-token").unwrap();
+        let auth = AuthConfig {
+            bearer_token: Some(Secret::Credential("idec-token".to_string())),
+            ..Default::default()
+        };
+
+        let request = build_with_env(&auth, &[("CREDENTIALS_DIRECTORY", "testdata/credentials")]);
+        assert_eq!(request.headers()[AUTHORIZATION], "Bearer credential-token");
+
+        // Clean up
+        std::fs::remove_dir_all("testdata/credentials").unwrap();
+    }
+
+    #[test]
+    fn test_missing_secret_fails() {
+        let auth = AuthConfig {
+            bearer_token: Some(Secret::Env("TEST_MISSING_SECRET".to_string())),
+            ..Default::default()
+        };
+        let request = reqwest::Client::new().get("https://idec.example.com/export");
+        assert!(apply(request, &auth).is_err());
+    }
+
+    #[test]
+    fn test_bearer_token_and_basic_auth_are_exclusive() {
+        let auth = AuthConfig {
+            bearer_token: Some(Secret::Env("TEST_TOKEN".to_string())),
+            basic: Some(BasicAuth {
+                username: "coredns".to_string(),
+                password: Secret::Env("TEST_PASSWORD".to_string()),
+            }),
+            ..Default::default()
+        };
+        let request = reqwest::Client::new().get("https://idec.example.com/export");
+        assert!(apply(request, &auth).is_err());
+    }
+}
@@ -0,0 +1,966 @@
+// axfr_fetcher.rs
+use crate::config::{ExternalSource, SourceKind, TsigAlgorithm, TsigKey};
+use crate::dns_record::{DnsRecord, RecordType};
+use crate::dns_record_collector::DnsRecordFetcher;
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use hmac::{Hmac, Mac};
+use log::debug;
+use reqwest::Url;
+use sha2::{Sha256, Sha512};
+use std::error::Error;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const DNS_PORT: u16 = 53;
+const TYPE_A: u16 = 1;
+const TYPE_CNAME: u16 = 5;
+const TYPE_SOA: u16 = 6;
+const TYPE_AAAA: u16 = 28;
+const TYPE_TSIG: u16 = 250;
+const TYPE_AXFR: u16 = 252;
+const CLASS_IN: u16 = 1;
+const CLASS_ANY: u16 = 255;
+// The allowed difference in seconds between the clocks of the server and this host, as recommended by RFC 8945
+const TSIG_FUDGE: u16 = 300;
+// RFC 8945 allows at most 99 unsigned messages between signed messages of a transfer
+const MAX_UNSIGNED_MESSAGES: usize = 99;
+
+/// Fetches the A, AAAA and CNAME records of a zone with a zone transfer (AXFR) from a DNS server, like a legacy BIND server.
+/// The transfer is optionally signed with a TSIG key, in which case the signatures of the server are verified as well.
+#[derive(Default)]
+pub struct AxfrDnsRecordFetcher;
+
+#[async_trait]
+impl DnsRecordFetcher for AxfrDnsRecordFetcher {
+    async fn fetch_dns_records(
+        &self,
+        source: &ExternalSource,
+        _ca_cert_base64: &str,
+    ) -> Result<Vec<DnsRecord>, Box<dyn Error>> {
+        let axfr = match &source.kind {
+            SourceKind::Axfr(axfr) => axfr,
+            _ => return Err("the source is not an axfr source".into()),
+        };
+        let zone = if axfr.zone.is_empty() {
+            &source.domain_name
+        } else {
+            &axfr.zone
+        };
+        let zone = zone.trim_end_matches('.').to_lowercase();
+        let address = server_address(&source.url)?;
+        let tsig = match &axfr.tsig {
+            Some(key) => Some(Tsig::new(key)?),
+            None => None,
+        };
+
+        let resource_records = transfer_zone(&address, &zone, tsig.as_ref()).await?;
+        Ok(to_dns_records(&resource_records, &zone, source))
+    }
+}
+
+// The host and port of a dns:// url, the scheme can be left out
+fn server_address(url: &str) -> Result<String, Box<dyn Error>> {
+    if url.is_empty() {
+        return Err("the url of the DNS server is not set".into());
+    }
+    let url = if url.contains("://") {
+        Url::parse(url)?
+    } else {
+        Url::parse(&format!("dns://{}", url))?
+    };
+    if url.scheme() != "dns" {
+        return Err(format!("{} is not a dns:// url", url).into());
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| format!("{} has no host", url))?;
+    Ok(format!("{}:{}", host, url.port().unwrap_or(DNS_PORT)))
+}
+
+// A resource record of the zone, with the value of the types that become DNS records
+#[derive(Debug, Clone, PartialEq)]
+struct ResourceRecord {
+    name: String,
+    record_type: u16,
+    ttl: u32,
+    value: String,
+}
+
+// A response of the server, only the parts needed for a zone transfer
+#[derive(Debug)]
+struct Message {
+    id: u16,
+    rcode: u8,
+    answers: Vec<ResourceRecord>,
+    tsig: Option<TsigRecord>,
+}
+
+// The TSIG record at the end of a signed message
+#[derive(Debug, Clone, PartialEq)]
+struct TsigRecord {
+    // Where the record starts in the message, the MAC covers the message up to here
+    offset: usize,
+    algorithm: String,
+    time_signed: u64,
+    fudge: u16,
+    mac: Vec<u8>,
+    original_id: u16,
+    error: u16,
+    other: Vec<u8>,
+}
+
+async fn transfer_zone(
+    address: &str,
+    zone: &str,
+    tsig: Option<&Tsig>,
+) -> Result<Vec<ResourceRecord>, Box<dyn Error>> {
+    let id: u16 = rand::random();
+    let mut query = build_query(id, zone);
+    let mut verifier = None;
+    if let Some(tsig) = tsig {
+        let (signed_query, request_mac) = tsig.sign_request(&query, id, unix_time()?);
+        query = signed_query;
+        verifier = Some(TsigVerifier::new(tsig, request_mac));
+    }
+
+    let mut stream = TcpStream::connect(address)
+        .await
+        .map_err(|e| format!("failed to connect to {} - {}", address, e))?;
+    stream
+        .write_all(&(query.len() as u16).to_be_bytes())
+        .await?;
+    stream.write_all(&query).await?;
+
+    // The records of the zone are between the SOA record the transfer starts and ends with
+    let mut resource_records = Vec::new();
+    let mut soa_records = 0;
+    while soa_records < 2 {
+        let mut length = [0u8; 2];
+        stream.read_exact(&mut length).await.map_err(|e| {
+            format!(
+                "the transfer of {} from {} ended before the closing SOA record - {}",
+                zone, address, e
+            )
+        })?;
+        let mut raw_message = vec![0u8; u16::from_be_bytes(length) as usize];
+        stream.read_exact(&mut raw_message).await?;
+
+        let message = parse_message(&raw_message)?;
+        if message.id != id {
+            return Err(format!("unexpected message id {} from {}", message.id, address).into());
+        }
+        if message.rcode != 0 {
+            return Err(match &message.tsig {
+                Some(record) if record.error != 0 => format!(
+                    "{} rejected the TSIG key - {}",
+                    address,
+                    tsig_error_name(record.error)
+                ),
+                _ => format!(
+                    "{} refused the transfer of {} - {}",
+                    address,
+                    zone,
+                    rcode_name(message.rcode)
+                ),
+            }
+            .into());
+        }
+        if let Some(verifier) = verifier.as_mut() {
+            verifier.verify(&raw_message, message.tsig.as_ref(), unix_time()?)?;
+        }
+
+        for resource_record in message.answers {
+            if resource_record.record_type == TYPE_SOA {
+                soa_records += 1;
+            } else if soa_records == 0 {
+                return Err(format!(
+                    "the transfer of {} from {} doesn't start with a SOA record",
+                    zone, address
+                )
+                .into());
+            } else {
+                resource_records.push(resource_record);
+            }
+        }
+    }
+    if let Some(verifier) = &verifier {
+        verifier.finish()?;
+    }
+    Ok(resource_records)
+}
+
+fn to_dns_records(
+    resource_records: &[ResourceRecord],
+    zone: &str,
+    source: &ExternalSource,
+) -> Vec<DnsRecord> {
+    let mut dns_records = Vec::new();
+    for resource_record in resource_records {
+        let record_type = match resource_record.record_type {
+            TYPE_A => RecordType::A,
+            TYPE_AAAA => RecordType::Aaaa,
+            TYPE_CNAME => RecordType::Cname,
+            other => {
+                debug!(
+                    "Skipping record {} of type {} in zone {}",
+                    resource_record.name, other, zone
+                );
+                continue;
+            }
+        };
+        dns_records.push(DnsRecord {
+            cluster_name: source.source_name.clone(),
+            controller: zone.to_string(),
+            fqdn: resource_record.name.clone(),
+            ip: resource_record.value.clone(),
+            kind: "ZoneTransfer".to_string(),
+            record_type,
+            ttl: Some(resource_record.ttl as u64),
+            ..Default::default()
+        });
+    }
+    dns_records
+}
+
+fn unix_time() -> Result<u64, Box<dyn Error>> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+fn build_query(id: u16, zone: &str) -> Vec<u8> {
+    let mut query = Vec::new();
+    query.extend(id.to_be_bytes());
+    // No flags, one question
+    query.extend([0, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+    query.extend(encode_name(zone));
+    query.extend(TYPE_AXFR.to_be_bytes());
+    query.extend(CLASS_IN.to_be_bytes());
+    query
+}
+
+// A name in wire format, lower case and without compression
+fn encode_name(name: &str) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for label in name
+        .trim_end_matches('.')
+        .split('.')
+        .filter(|label| !label.is_empty())
+    {
+        encoded.push(label.len() as u8);
+        encoded.extend(label.to_lowercase().as_bytes());
+    }
+    encoded.push(0);
+    encoded
+}
+
+// Reads the fields of a message, names can point to earlier names in the message (compression)
+struct MessageReader<'a> {
+    message: &'a [u8],
+    position: usize,
+}
+
+impl<'a> MessageReader<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        let bytes = self
+            .message
+            .get(self.position..self.position + length)
+            .ok_or("the message of the server is truncated")?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, Box<dyn Error>> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u48(&mut self) -> Result<u64, Box<dyn Error>> {
+        let bytes = self.bytes(6)?;
+        Ok(bytes
+            .iter()
+            .fold(0, |time, byte| (time << 8) | *byte as u64))
+    }
+
+    fn name(&mut self) -> Result<String, Box<dyn Error>> {
+        let mut labels = Vec::new();
+        let mut position = self.position;
+        let mut end = None;
+        let mut pointers = 0;
+        loop {
+            let length = *self
+                .message
+                .get(position)
+                .ok_or("the message of the server is truncated")? as usize;
+            if length & 0xC0 == 0xC0 {
+                let low = *self
+                    .message
+                    .get(position + 1)
+                    .ok_or("the message of the server is truncated")?
+                    as usize;
+                end.get_or_insert(position + 2);
+                pointers += 1;
+                if pointers > 64 {
+                    return Err("the message of the server has a compression loop".into());
+                }
+                position = ((length & 0x3F) << 8) | low;
+            } else if length == 0 {
+                self.position = end.unwrap_or(position + 1);
+                return Ok(labels.join("."));
+            } else {
+                let label = self
+                    .message
+                    .get(position + 1..position + 1 + length)
+                    .ok_or("the message of the server is truncated")?;
+                labels.push(String::from_utf8_lossy(label).to_lowercase());
+                position += 1 + length;
+            }
+        }
+    }
+}
+
+fn parse_message(raw_message: &[u8]) -> Result<Message, Box<dyn Error>> {
+    let mut reader = MessageReader {
+        message: raw_message,
+        position: 0,
+    };
+    let id = reader.u16()?;
+    let flags = reader.u16()?;
+    let questions = reader.u16()?;
+    let answers = reader.u16()?;
+    let authorities = reader.u16()?;
+    let additionals = reader.u16()?;
+    for _ in 0..questions {
+        reader.name()?;
+        reader.bytes(4)?;
+    }
+
+    let mut message = Message {
+        id,
+        rcode: (flags & 0x000F) as u8,
+        answers: Vec::new(),
+        tsig: None,
+    };
+    let records = answers as usize + authorities as usize + additionals as usize;
+    for index in 0..records {
+        let offset = reader.position;
+        let name = reader.name()?;
+        let record_type = reader.u16()?;
+        let _class = reader.u16()?;
+        let ttl = reader.u32()?;
+        let length = reader.u16()? as usize;
+        let end = reader.position + length;
+        let value = match record_type {
+            TYPE_A => Ipv4Addr::from(<[u8; 4]>::try_from(reader.bytes(length)?)?).to_string(),
+            TYPE_AAAA => Ipv6Addr::from(<[u8; 16]>::try_from(reader.bytes(length)?)?).to_string(),
+            TYPE_CNAME => reader.name()?,
+            TYPE_TSIG => {
+                // RFC 8945 puts the TSIG record last in the additional section, the signature covers everything before it
+                if index + 1 != records || additionals == 0 {
+                    return Err(
+                        "the TSIG record of the server is not the last additional record".into(),
+                    );
+                }
+                message.tsig = Some(TsigRecord {
+                    offset,
+                    algorithm: reader.name()?,
+                    time_signed: reader.u48()?,
+                    fudge: reader.u16()?,
+                    mac: {
+                        let mac_length = reader.u16()? as usize;
+                        reader.bytes(mac_length)?.to_vec()
+                    },
+                    original_id: reader.u16()?,
+                    error: reader.u16()?,
+                    other: {
+                        let other_length = reader.u16()? as usize;
+                        reader.bytes(other_length)?.to_vec()
+                    },
+                });
+                "".to_string()
+            }
+            _ => "".to_string(),
+        };
+        reader.position = end;
+        if index < answers as usize {
+            message.answers.push(ResourceRecord {
+                name,
+                record_type,
+                ttl,
+                value,
+            });
+        }
+    }
+    Ok(message)
+}
+
+fn rcode_name(rcode: u8) -> String {
+    match rcode {
+        1 => "FORMERR".to_string(),
+        2 => "SERVFAIL".to_string(),
+        3 => "NXDOMAIN".to_string(),
+        4 => "NOTIMP".to_string(),
+        5 => "REFUSED".to_string(),
+        9 => "NOTAUTH".to_string(),
+        rcode => format!("RCODE {}", rcode),
+    }
+}
+
+fn tsig_error_name(error: u16) -> String {
+    match error {
+        16 => "BADSIG".to_string(),
+        17 => "BADKEY".to_string(),
+        18 => "BADTIME".to_string(),
+        22 => "BADTRUNC".to_string(),
+        error => format!("error {}", error),
+    }
+}
+
+// A TSIG key (RFC 8945) with its secret resolved
+struct Tsig {
+    name: String,
+    algorithm: TsigAlgorithm,
+    secret: Vec<u8>,
+}
+
+impl Tsig {
+    fn new(key: &TsigKey) -> Result<Tsig, Box<dyn Error>> {
+        let secret = general_purpose::STANDARD
+            .decode(key.secret.resolve()?.trim())
+            .map_err(|e| format!("the secret of TSIG key {} is not base64 - {}", key.name, e))?;
+        Ok(Tsig {
+            name: key.name.trim_end_matches('.').to_lowercase(),
+            algorithm: key.algorithm,
+            secret,
+        })
+    }
+
+    fn algorithm_name(&self) -> &'static str {
+        match self.algorithm {
+            TsigAlgorithm::HmacSha256 => "hmac-sha256",
+            TsigAlgorithm::HmacSha512 => "hmac-sha512",
+        }
+    }
+
+    fn mac(&self, data: &[u8]) -> Vec<u8> {
+        match self.algorithm {
+            TsigAlgorithm::HmacSha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret)
+                    .expect("HMAC accepts keys of any length");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+            TsigAlgorithm::HmacSha512 => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(&self.secret)
+                    .expect("HMAC accepts keys of any length");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+
+    // Compares in constant time
+    fn verify_mac(&self, data: &[u8], expected: &[u8]) -> bool {
+        match self.algorithm {
+            TsigAlgorithm::HmacSha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret)
+                    .expect("HMAC accepts keys of any length");
+                mac.update(data);
+                mac.verify_slice(expected).is_ok()
+            }
+            TsigAlgorithm::HmacSha512 => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(&self.secret)
+                    .expect("HMAC accepts keys of any length");
+                mac.update(data);
+                mac.verify_slice(expected).is_ok()
+            }
+        }
+    }
+
+    // The TSIG variables a MAC covers, after the message itself
+    fn variables(&self, time_signed: u64, fudge: u16, error: u16, other: &[u8]) -> Vec<u8> {
+        let mut variables = encode_name(&self.name);
+        variables.extend(CLASS_ANY.to_be_bytes());
+        variables.extend(0u32.to_be_bytes());
+        variables.extend(encode_name(self.algorithm_name()));
+        variables.extend(&time_signed.to_be_bytes()[2..]);
+        variables.extend(fudge.to_be_bytes());
+        variables.extend(error.to_be_bytes());
+        variables.extend((other.len() as u16).to_be_bytes());
+        variables.extend(other);
+        variables
+    }
+
+    // The message with a TSIG record with the MAC added to the additional section
+    fn append_record(&self, message: &[u8], id: u16, time_signed: u64, mac: &[u8]) -> Vec<u8> {
+        let mut rdata = encode_name(self.algorithm_name());
+        rdata.extend(&time_signed.to_be_bytes()[2..]);
+        rdata.extend(TSIG_FUDGE.to_be_bytes());
+        rdata.extend((mac.len() as u16).to_be_bytes());
+        rdata.extend(mac);
+        rdata.extend(id.to_be_bytes());
+        // No error and no other data
+        rdata.extend([0, 0, 0, 0]);
+
+        let mut signed = message.to_vec();
+        let additionals = u16::from_be_bytes([signed[10], signed[11]]) + 1;
+        signed[10..12].copy_from_slice(&additionals.to_be_bytes());
+        signed.extend(encode_name(&self.name));
+        signed.extend(TYPE_TSIG.to_be_bytes());
+        signed.extend(CLASS_ANY.to_be_bytes());
+        signed.extend(0u32.to_be_bytes());
+        signed.extend((rdata.len() as u16).to_be_bytes());
+        signed.extend(rdata);
+        signed
+    }
+
+    // The signed query and its MAC, which the MAC of the first response covers
+    fn sign_request(&self, query: &[u8], id: u16, now: u64) -> (Vec<u8>, Vec<u8>) {
+        let mut data = query.to_vec();
+        data.extend(self.variables(now, TSIG_FUDGE, 0, &[]));
+        let mac = self.mac(&data);
+        (self.append_record(query, id, now, &mac), mac)
+    }
+}
+
+// Verifies the signatures of the messages of a transfer, each MAC covers the MAC before it.
+// Messages without a TSIG record are covered by the MAC of the next signed message.
+struct TsigVerifier<'a> {
+    tsig: &'a Tsig,
+    prior_mac: Vec<u8>,
+    unsigned: Vec<u8>,
+    unsigned_messages: usize,
+    first: bool,
+}
+
+impl<'a> TsigVerifier<'a> {
+    fn new(tsig: &'a Tsig, request_mac: Vec<u8>) -> TsigVerifier<'a> {
+        TsigVerifier {
+            tsig,
+            prior_mac: request_mac,
+            unsigned: Vec::new(),
+            unsigned_messages: 0,
+            first: true,
+        }
+    }
+
+    fn verify(
+        &mut self,
+        raw_message: &[u8],
+        record: Option<&TsigRecord>,
+        now: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        let record = match record {
+            Some(record) => record,
+            None if self.first || self.unsigned_messages == MAX_UNSIGNED_MESSAGES => {
+                return Err(format!(
+                    "the transfer is not signed with TSIG key {}",
+                    self.tsig.name
+                )
+                .into())
+            }
+            None => {
+                self.unsigned.extend(raw_message);
+                self.unsigned_messages += 1;
+                return Ok(());
+            }
+        };
+        if record.algorithm != self.tsig.algorithm_name() {
+            return Err(format!(
+                "the transfer is signed with {} instead of {}",
+                record.algorithm,
+                self.tsig.algorithm_name()
+            )
+            .into());
+        }
+
+        // The message as it was before the TSIG record was added
+        let mut message = raw_message[..record.offset].to_vec();
+        let additionals = u16::from_be_bytes([message[10], message[11]])
+            .checked_sub(1)
+            .ok_or("the TSIG record of the transfer is not in the additional section")?;
+        message[10..12].copy_from_slice(&additionals.to_be_bytes());
+        message[0..2].copy_from_slice(&record.original_id.to_be_bytes());
+
+        let data = response_digest_data(
+            self.tsig,
+            &self.prior_mac,
+            &self.unsigned,
+            &message,
+            record,
+            self.first,
+        );
+        if !self.tsig.verify_mac(&data, &record.mac) {
+            return Err(format!(
+                "the TSIG signature of the transfer doesn't match key {}",
+                self.tsig.name
+            )
+            .into());
+        }
+        if now.abs_diff(record.time_signed) > record.fudge as u64 {
+            return Err(
+                "the transfer was signed too long ago, are the clocks of both hosts in sync?"
+                    .into(),
+            );
+        }
+
+        self.prior_mac = record.mac.clone();
+        self.unsigned.clear();
+        self.unsigned_messages = 0;
+        self.first = false;
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<(), Box<dyn Error>> {
+        if self.unsigned_messages > 0 {
+            return Err("the last message of the transfer is not signed".into());
+        }
+        Ok(())
+    }
+}
+
+// The data the MAC of a response covers: the prior MAC, the unsigned messages since, the message itself,
+// and all TSIG variables for the first response or only the timers for the next ones
+fn response_digest_data(
+    tsig: &Tsig,
+    prior_mac: &[u8],
+    unsigned: &[u8],
+    message: &[u8],
+    record: &TsigRecord,
+    first: bool,
+) -> Vec<u8> {
+    let mut data = (prior_mac.len() as u16).to_be_bytes().to_vec();
+    data.extend(prior_mac);
+    data.extend(unsigned);
+    data.extend(message);
+    if first {
+        data.extend(tsig.variables(
+            record.time_signed,
+            record.fudge,
+            record.error,
+            &record.other,
+        ));
+    } else {
+        data.extend(&record.time_signed.to_be_bytes()[2..]);
+        data.extend(record.fudge.to_be_bytes());
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AxfrConfig, Secret};
+    use crate::test_support::test_source;
+    use tokio::net::TcpListener;
+
+    fn axfr_source(url: &str, tsig: Option<TsigKey>) -> ExternalSource {
+        let axfr = AxfrConfig {
+            zone: "".to_string(),
+            tsig,
+        };
+        test_source("legacy-bind", "home.lab", url, SourceKind::Axfr(axfr))
+    }
+
+    fn tsig_key(secret_file: &str) -> TsigKey {
+        TsigKey {
+            name: "transfer-key".to_string(),
+            algorithm: TsigAlgorithm::HmacSha256,
+            secret: Secret::File(format!("testdata/axfr/{}", secret_file)),
+        }
+    }
+
+    // A resource record of the fixture zone in wire format, the apex is compressed to the name in the question
+    fn encode_resource_record(line: &str) -> Vec<u8> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let mut encoded = match fields[0] {
+            "@" => vec![0xC0, 12],
+            name => encode_name(&format!("{}.home.lab", name)),
+        };
+        let (record_type, rdata) = match fields[2] {
+            "A" => (
+                TYPE_A,
+                fields[3].parse::<Ipv4Addr>().unwrap().octets().to_vec(),
+            ),
+            "AAAA" => (
+                TYPE_AAAA,
+                fields[3].parse::<Ipv6Addr>().unwrap().octets().to_vec(),
+            ),
+            "CNAME" => (TYPE_CNAME, encode_name(fields[3])),
+            "MX" => {
+                let mut rdata = fields[3].parse::<u16>().unwrap().to_be_bytes().to_vec();
+                rdata.extend(encode_name(fields[4]));
+                (15, rdata)
+            }
+            "SOA" => (TYPE_SOA, vec![0; 22]),
+            other => panic!("Unsupported record type {} in the fixture zone", other),
+        };
+        encoded.extend(record_type.to_be_bytes());
+        encoded.extend(CLASS_IN.to_be_bytes());
+        encoded.extend(fields[1].parse::<u32>().unwrap().to_be_bytes());
+        encoded.extend((rdata.len() as u16).to_be_bytes());
+        encoded.extend(rdata);
+        encoded
+    }
+
+    fn response(id: u16, resource_records: &[Vec<u8>]) -> Vec<u8> {
+        let mut message = id.to_be_bytes().to_vec();
+        // An authoritative answer
+        message.extend([0x84, 0x00, 0, 1]);
+        message.extend((resource_records.len() as u16).to_be_bytes());
+        message.extend([0, 0, 0, 0]);
+        message.extend(encode_name("home.lab"));
+        message.extend(TYPE_AXFR.to_be_bytes());
+        message.extend(CLASS_IN.to_be_bytes());
+        for resource_record in resource_records {
+            message.extend(resource_record);
+        }
+        message
+    }
+
+    // A DNS server transferring the fixture zone in three messages, of which the middle one is not signed.
+    // With a TSIG key, a query that isn't signed with the key is answered with NOTAUTH.
+    async fn start_dns_server(tsig: Option<Tsig>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("dns://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut length = [0u8; 2];
+            stream.read_exact(&mut length).await.unwrap();
+            let mut query = vec![0u8; u16::from_be_bytes(length) as usize];
+            stream.read_exact(&mut query).await.unwrap();
+            let id = u16::from_be_bytes([query[0], query[1]]);
+
+            let zone = std::fs::read_to_string("testdata/axfr/home.lab.zone").unwrap();
+            let resource_records: Vec<Vec<u8>> = zone
+                .lines()
+                .filter(|line| !line.starts_with(';') && !line.trim().is_empty())
+                .map(encode_resource_record)
+                .collect();
+            let soa = resource_records[0].clone();
+            let middle = resource_records.len() / 2;
+            let mut messages = vec![
+                response(id, &resource_records[..middle]),
+                response(id, &resource_records[middle..]),
+                response(id, &[soa]),
+            ];
+
+            if let Some(tsig) = &tsig {
+                let request = parse_message(&query).unwrap().tsig;
+                let request_mac = match &request {
+                    Some(record) => {
+                        let mut unsigned_query = query[..record.offset].to_vec();
+                        unsigned_query[11] -= 1;
+                        unsigned_query.extend(tsig.variables(
+                            record.time_signed,
+                            record.fudge,
+                            0,
+                            &[],
+                        ));
+                        Some(record.mac.clone()).filter(|mac| tsig.verify_mac(&unsigned_query, mac))
+                    }
+                    None => None,
+                };
+                match request_mac {
+                    Some(request_mac) => {
+                        let now = unix_time().unwrap();
+                        let mut prior_mac = request_mac;
+                        let mut unsigned = Vec::new();
+                        for (index, message) in messages.iter_mut().enumerate() {
+                            if index == 1 {
+                                unsigned = message.clone();
+                                continue;
+                            }
+                            let record = TsigRecord {
+                                offset: message.len(),
+                                algorithm: tsig.algorithm_name().to_string(),
+                                time_signed: now,
+                                fudge: TSIG_FUDGE,
+                                mac: vec![],
+                                original_id: id,
+                                error: 0,
+                                other: vec![],
+                            };
+                            let data = response_digest_data(
+                                tsig,
+                                &prior_mac,
+                                &unsigned,
+                                message,
+                                &record,
+                                index == 0,
+                            );
+                            prior_mac = tsig.mac(&data);
+                            unsigned.clear();
+                            *message = tsig.append_record(message, id, now, &prior_mac);
+                        }
+                    }
+                    None => {
+                        // NOTAUTH, with a TSIG record with error BADSIG and without a MAC
+                        let mut refusal = response(id, &[]);
+                        refusal[3] = 9;
+                        let mut refusal = tsig.append_record(&refusal, id, 0, &[]);
+                        let error_offset = refusal.len() - 4;
+                        refusal[error_offset..error_offset + 2]
+                            .copy_from_slice(&16u16.to_be_bytes());
+                        messages = vec![refusal];
+                    }
+                }
+            }
+
+            for message in messages {
+                stream
+                    .write_all(&(message.len() as u16).to_be_bytes())
+                    .await
+                    .unwrap();
+                stream.write_all(&message).await.unwrap();
+            }
+        });
+        url
+    }
+
+    fn summary(dns_records: &[DnsRecord]) -> Vec<(&str, &str, RecordType, Option<u64>)> {
+        dns_records
+            .iter()
+            .map(|r| (r.fqdn.as_str(), r.ip.as_str(), r.record_type, r.ttl))
+            .collect()
+    }
+
+    fn expected_records() -> Vec<(&'static str, &'static str, RecordType, Option<u64>)> {
+        vec![
+            ("home.lab", "192.168.178.2", RecordType::A, Some(3600)),
+            ("ns1.home.lab", "192.168.178.2", RecordType::A, Some(3600)),
+            ("nas.home.lab", "192.168.178.10", RecordType::A, Some(300)),
+            ("nas.home.lab", "fd00::10", RecordType::Aaaa, Some(300)),
+            (
+                "files.home.lab",
+                "nas.home.lab",
+                RecordType::Cname,
+                Some(3600),
+            ),
+            (
+                "printer.home.lab",
+                "192.168.178.30",
+                RecordType::A,
+                Some(3600),
+            ),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_fetch_records_with_zone_transfer() {
+        let url = start_dns_server(None).await;
+
+        let dns_records = AxfrDnsRecordFetcher
+            .fetch_dns_records(&axfr_source(&url, None), "")
+            .await
+            .unwrap();
+
+        assert_eq!(summary(&dns_records), expected_records());
+        assert!(dns_records
+            .iter()
+            .all(|r| r.kind == "ZoneTransfer" && r.cluster_name == "legacy-bind"));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_records_with_tsig_signed_zone_transfer() {
+        let key = tsig_key("transfer-key.secret");
+        let url = start_dns_server(Some(Tsig::new(&key).unwrap())).await;
+
+        let dns_records = AxfrDnsRecordFetcher
+            .fetch_dns_records(&axfr_source(&url, Some(key)), "")
+            .await
+            .unwrap();
+
+        assert_eq!(summary(&dns_records), expected_records());
+    }
+
+    #[tokio::test]
+    async fn test_zone_transfer_fails_with_other_tsig_key() {
+        let server_key = tsig_key("transfer-key.secret");
+        let url = start_dns_server(Some(Tsig::new(&server_key).unwrap())).await;
+        let client_key = tsig_key("other-key.secret");
+
+        let error = AxfrDnsRecordFetcher
+            .fetch_dns_records(&axfr_source(&url, Some(client_key)), "")
+            .await
+            .unwrap_err();
+
+        assert!(
+            error.to_string().contains("rejected the TSIG key - BADSIG"),
+            "Unexpected error: {}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unsigned_zone_transfer_fails_with_tsig_key() {
+        let url = start_dns_server(None).await;
+        let key = tsig_key("transfer-key.secret");
+
+        let error = AxfrDnsRecordFetcher
+            .fetch_dns_records(&axfr_source(&url, Some(key)), "")
+            .await
+            .unwrap_err();
+
+        assert!(
+            error.to_string().contains("not signed"),
+            "Unexpected error: {}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_tsig_record_in_answer_section_is_rejected() {
+        let tsig = Tsig::new(&tsig_key("transfer-key.secret")).unwrap();
+        // The TSIG record is counted as an answer, ARCOUNT stays 0
+        let mut message = tsig.append_record(&response(7, &[]), 7, unix_time().unwrap(), &[0; 32]);
+        message[7] = 1;
+        message[11] = 0;
+
+        let error = parse_message(&message).unwrap_err();
+        assert!(
+            error.to_string().contains("not the last additional record"),
+            "Unexpected error: {}",
+            error
+        );
+
+        let record = TsigRecord {
+            offset: response(7, &[]).len(),
+            algorithm: tsig.algorithm_name().to_string(),
+            time_signed: 0,
+            fudge: TSIG_FUDGE,
+            mac: vec![0; 32],
+            original_id: 7,
+            error: 0,
+            other: vec![],
+        };
+        let mut verifier = TsigVerifier::new(&tsig, vec![0; 32]);
+        let error = verifier.verify(&message, Some(&record), 0).unwrap_err();
+        assert!(
+            error.to_string().contains("not in the additional section"),
+            "Unexpected error: {}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_server_address() {
+        assert_eq!(
+            server_address("dns://192.168.178.2").unwrap(),
+            "192.168.178.2:53"
+        );
+        assert_eq!(
+            server_address("ns1.home.lab:5353").unwrap(),
+            "ns1.home.lab:5353"
+        );
+        assert_eq!(server_address("dns://[fd00::2]").unwrap(), "[fd00::2]:53");
+        assert!(server_address("http://ns1.home.lab").is_err());
+        assert!(server_address("").is_err());
+    }
+}
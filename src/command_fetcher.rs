@@ -0,0 +1,152 @@
+// command_fetcher.rs
+use crate::config::{ExternalSource, SourceKind};
+use crate::dns_record::DnsRecord;
+use crate::dns_record_collector::DnsRecordFetcher;
+use async_trait::async_trait;
+use log::debug;
+use std::error::Error;
+use std::process::Stdio;
+use tokio::process::Command;
+
+/// Fetches DNS records from the output of a command, for one-off integrations like an inventory script.
+/// The command writes the records as JSON to stdout, in the same shape as the IDEC export.
+/// A non-zero exit is a failed fetch; the timeout of the source kills the command.
+#[derive(Default)]
+pub struct CommandDnsRecordFetcher;
+
+#[async_trait]
+impl DnsRecordFetcher for CommandDnsRecordFetcher {
+    async fn fetch_dns_records(
+        &self,
+        source: &ExternalSource,
+        _ca_cert_base64: &str,
+    ) -> Result<Vec<DnsRecord>, Box<dyn Error>> {
+        let command = match &source.kind {
+            SourceKind::Command(command) => command,
+            _ => return Err("the source is not a command source".into()),
+        };
+
+        debug!(
+            "Running {} {:?} for source {}",
+            command.command, command.args, source.source_name
+        );
+        let mut process = Command::new(&command.command);
+        process
+            .args(&command.args)
+            .envs(&command.env)
+            .stdin(Stdio::null())
+            // The DnsRecordCollector drops the fetch when the source times out, which must not leave the command running
+            .kill_on_drop(true);
+        if let Some(working_directory) = &command.working_directory {
+            process.current_dir(working_directory);
+        }
+        let output = process
+            .output()
+            .await
+            .map_err(|e| format!("failed to run {} - {}", command.command, e))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!(
+                "{} failed with {} - {}",
+                command.command,
+                output.status,
+                stderr.trim()
+            )
+            .into());
+        }
+        let dns_records: Vec<DnsRecord> = serde_json::from_slice(&output.stdout).map_err(|e| {
+            format!(
+                "the output of {} is not a JSON list of DNS records - {}",
+                command.command, e
+            )
+        })?;
+        Ok(dns_records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CommandSourceConfig, Config, RetryPolicy};
+    use crate::dns_record_collector::DnsRecordCollector;
+    use crate::test_support::test_source;
+    use std::collections::BTreeMap;
+    use tokio::time::{Duration, Instant};
+
+    fn shell_source(script: &str) -> ExternalSource {
+        let command = CommandSourceConfig {
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            env: BTreeMap::from([("PROXMOX_NODE".to_string(), "pve1".to_string())]),
+            ..Default::default()
+        };
+        ExternalSource {
+            retry: RetryPolicy {
+                max_attempts: 1,
+                ..Default::default()
+            },
+            ..test_source("proxmox", "home.lab", "", SourceKind::Command(command))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_records_from_command_output() {
+        // The same fields as the IDEC export
+        let source = shell_source(
+            r#"cat <<EOF
+[{"clusterIP": "", "clusterName": "proxmox", "controller": "qemu", "fqdn": "$PROXMOX_NODE.home.lab",
+  "ip": "192.168.178.20", "kind": "VM", "namespace": "", "port": ""}]
+EOF"#,
+        );
+
+        let dns_records = CommandDnsRecordFetcher
+            .fetch_dns_records(&source, "")
+            .await
+            .unwrap();
+
+        assert_eq!(dns_records.len(), 1);
+        assert_eq!(dns_records[0].fqdn, "pve1.home.lab");
+        assert_eq!(dns_records[0].ip, "192.168.178.20");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_fails_on_non_zero_exit() {
+        let source = shell_source("echo 'inventory unavailable' >&2; exit 3");
+
+        let error = CommandDnsRecordFetcher
+            .fetch_dns_records(&source, "")
+            .await
+            .unwrap_err();
+
+        let error = error.to_string();
+        assert!(
+            error.contains("exit status: 3"),
+            "Unexpected error: {}",
+            error
+        );
+        assert!(
+            error.contains("inventory unavailable"),
+            "Unexpected error: {}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_collector_continues_when_command_times_out() {
+        let config = Config {
+            external_sources: vec![ExternalSource {
+                timeout_in_seconds: Some(1),
+                ..shell_source("sleep 10")
+            }],
+            ..Default::default()
+        };
+        let mut collector = DnsRecordCollector::new(config, Box::new(CommandDnsRecordFetcher));
+
+        let start = Instant::now();
+        let records_map = collector.collect_dns_records().await.unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(records_map.is_empty());
+    }
+}
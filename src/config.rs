@@ -1,17 +1,39 @@
+use log::warn;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::{error::Error, fmt, fs};
-use log::{info};
+use crate::dns_record::relative_name;
+use crate::duplicate_policy::DuplicatePolicies;
+use crate::fqdn_rewriter::FqdnRewriter;
+use crate::record_filter::RecordFilter;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Config {
     pub external_sources: Vec<ExternalSource>,
+    // Not used with zones, every zone has its own destination file and source files
+    #[serde(default)]
     pub destination_file_path: String,
+    #[serde(default)]
     pub source_file_paths: Vec<String>,
     pub temp_storage_path: String,
     pub ttl: u64,
     pub call_frequency_in_minutes: u64,
     pub ca_cert_base64: String,
     pub log_level: String,
+    #[serde(default = "default_fetch_timeout_in_seconds")]
+    pub fetch_timeout_in_seconds: u64,
+    #[serde(default = "default_cycle_timeout_in_seconds")]
+    pub cycle_timeout_in_seconds: u64,
+    // How long the last successfully fetched records of a source are used while the source fails
+    #[serde(default = "default_max_staleness_in_minutes")]
+    pub max_staleness_in_minutes: u64,
+    // A destination zone file per domain, the records are routed to the zone with the longest matching origin.
+    // Without zones, all records are merged into destination_file_path, relative to the domain name of their source.
+    #[serde(default)]
+    pub zones: Vec<ZoneConfig>,
+    // How records with the same name from different sources are resolved
+    #[serde(default)]
+    pub duplicates: DuplicatesConfig,
 }
 
 impl Default for Config {
@@ -25,19 +47,635 @@ impl Default for Config {
             call_frequency_in_minutes: 1,
             ca_cert_base64: "".to_string(),
             log_level: "".to_string(),
+            fetch_timeout_in_seconds: default_fetch_timeout_in_seconds(),
+            cycle_timeout_in_seconds: default_cycle_timeout_in_seconds(),
+            max_staleness_in_minutes: default_max_staleness_in_minutes(),
+            zones: vec![],
+            duplicates: DuplicatesConfig::default(),
         }
     }
 }
 
+impl Config {
+    // The zone a record with the FQDN belongs to, the zone with the longest origin the FQDN is part of
+    pub fn zone_for(&self, fqdn: &str) -> Option<&ZoneConfig> {
+        self.zones
+            .iter()
+            .filter(|zone| relative_name(fqdn, &zone.origin).is_some())
+            .max_by_key(|zone| zone.origin.trim_end_matches('.').len())
+    }
+}
+
+fn default_fetch_timeout_in_seconds() -> u64 {
+    10
+}
+
+fn default_cycle_timeout_in_seconds() -> u64 {
+    60
+}
+
+fn default_max_staleness_in_minutes() -> u64 {
+    24 * 60
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ExternalSource {
+    // The type of the source and its type specific settings, an IDEC export endpoint when the type is not set
+    #[serde(flatten, deserialize_with = "deserialize_source_kind")]
+    pub kind: SourceKind,
+    // The endpoint of the source, its meaning depends on the type of the source
+    #[serde(default)]
     pub url: String,
     pub domain_name: String,
     pub source_name: String,
+    // Overrides Config.fetch_timeout_in_seconds for this source
+    #[serde(default)]
+    pub timeout_in_seconds: Option<u64>,
+    // Overrides Config.call_frequency_in_minutes for this source
+    #[serde(default)]
+    pub interval_in_seconds: Option<u64>,
+    // A random delay of up to this many seconds is added to every interval, so sources with the same interval spread out
+    #[serde(default)]
+    pub jitter_in_seconds: u64,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    // Overrides Config.max_staleness_in_minutes for this source
+    #[serde(default)]
+    pub max_staleness_in_minutes: Option<u64>,
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    // Keeps the records up to date with a stream of changes, instead of polling, only for idec sources
+    #[serde(default)]
+    pub stream: Option<StreamConfig>,
+    // Which records of the source are used
+    #[serde(default)]
+    pub filters: RecordFilters,
+    // Rewrites the names of the records of the source, in order, before they are written to the zone
+    #[serde(default)]
+    pub rewrites: Vec<RewriteRule>,
+}
+
+// The kinds of sources, selected with the type field of an external source.
+// The settings of a kind are part of the external source itself, next to the type.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SourceKind {
+    // The export endpoint of an Ingress DNS Export Controller (IDEC) at the url of the source
+    Idec,
+    // The Kubernetes API, directly instead of through IDEC
+    Kubernetes(KubernetesConfig),
+    // The labels of the containers of a Docker Engine
+    Docker(DockerConfig),
+    // Static records in a YAML, JSON or CSV file
+    File(FileSourceConfig),
+    // The JSON records a command writes to stdout
+    Command(CommandSourceConfig),
+    // Any HTTP JSON inventory at the url of the source, mapped to records with JSON pointers
+    Http(HttpSourceConfig),
+    // The active leases of a DHCP server, regenerated when the lease file changes
+    Dhcp(DhcpSourceConfig),
+    // The services in the catalog of a Consul agent at the url of the source, regenerated when the catalog changes
+    Consul(ConsulConfig),
+    // The A, AAAA and CNAME records of a zone, transferred (AXFR) from the DNS server at the url of the source
+    Axfr(AxfrConfig),
+}
+
+impl SourceKind {
+    // The value of the type field, used to find the fetcher for the source
+    pub fn name(&self) -> &'static str {
+        match self {
+            SourceKind::Idec => "idec",
+            SourceKind::Kubernetes(_) => "kubernetes",
+            SourceKind::Docker(_) => "docker",
+            SourceKind::File(_) => "file",
+            SourceKind::Command(_) => "command",
+            SourceKind::Http(_) => "http",
+            SourceKind::Dhcp(_) => "dhcp",
+            SourceKind::Consul(_) => "consul",
+            SourceKind::Axfr(_) => "axfr",
+        }
+    }
+}
+
+// Sources configured before the type field existed are IDEC export endpoints.
+// The kind gets the fields the external source doesn't know, so a field the kind doesn't know either is a typo,
+// the kind structs can't deny unknown fields themselves because they are flattened.
+fn deserialize_source_kind<'de, D>(deserializer: D) -> Result<SourceKind, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let mut source = serde_json::Value::deserialize(deserializer)?;
+    if let Some(source) = source.as_object_mut() {
+        source
+            .entry("type")
+            .or_insert_with(|| serde_json::Value::String("idec".to_string()));
+    }
+    let kind = SourceKind::deserialize(&source).map_err(serde::de::Error::custom)?;
+    let known_fields = serde_json::to_value(&kind).map_err(serde::de::Error::custom)?;
+    if let (Some(fields), Some(known_fields)) = (source.as_object(), known_fields.as_object()) {
+        if let Some(field) = fields.keys().find(|field| !known_fields.contains_key(*field)) {
+            return Err(serde::de::Error::custom(format!("unknown field `{}` for source type {}", field, kind.name())));
+        }
+    }
+    Ok(kind)
+}
+
+impl Default for ExternalSource {
+    fn default() -> Self {
+        ExternalSource {
+            kind: SourceKind::Idec,
+            url: "".to_string(),
+            domain_name: "".to_string(),
+            source_name: "".to_string(),
+            timeout_in_seconds: None,
+            interval_in_seconds: None,
+            jitter_in_seconds: 0,
+            retry: RetryPolicy::default(),
+            max_staleness_in_minutes: None,
+            auth: None,
+            tls: None,
+            stream: None,
+            filters: RecordFilters::default(),
+            rewrites: Vec::new(),
+        }
+    }
+}
+
+// Settings of a kubernetes source.
+// Connection to the Kubernetes API of a cluster, either through a kubeconfig file,
+// or through the url, auth and tls settings of the external source.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct KubernetesConfig {
+    pub kubeconfig_path: Option<String>,
+    // The context of the kubeconfig to use, the current context when not set
+    pub context: Option<String>,
+    // Only list resources in this namespace, all namespaces when not set
+    pub namespace: Option<String>,
+    // The annotation containing the comma separated hostnames of a resource
+    pub hostname_annotation: String,
+    // The kinds of resources to read the hostnames from
+    pub resources: Vec<KubernetesResource>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum KubernetesResource {
+    // LoadBalancer Services with a hostname annotation
+    Services,
+    // Ingresses, with the hosts of their rules
+    Ingresses,
+    // Gateway API HTTPRoutes, with the address of their parent Gateways
+    HttpRoutes,
+    // Istio VirtualServices, with the address of the ingress gateway Service selected by their Gateways
+    VirtualServices,
+}
+
+impl Default for KubernetesConfig {
+    fn default() -> Self {
+        KubernetesConfig {
+            kubeconfig_path: None,
+            context: None,
+            namespace: None,
+            hostname_annotation: "external-dns.alpha.kubernetes.io/hostname".to_string(),
+            resources: vec![KubernetesResource::Services],
+        }
+    }
+}
+
+// Settings of a docker source.
+// Containers of a Docker Engine, reached through the url of the external source:
+// a unix socket (unix:///var/run/docker.sock, the default when the url is empty) or TCP (tcp://, http:// or https://).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct DockerConfig {
+    // The label containing the comma separated hostnames of a container
+    pub hostname_label: String,
+    // The IP address of the Docker host the records point to.
+    // When not set, the host IP a port of the container is published on is used.
+    pub host_ip: Option<String>,
+}
+
+impl Default for DockerConfig {
+    fn default() -> Self {
+        DockerConfig {
+            hostname_label: "coredns-k8s-sync.hostname".to_string(),
+            host_ip: None,
+        }
+    }
+}
+
+// Settings of a file source.
+// Every entry of the file has a fqdn and ip, and optionally a type (A, AAAA or CNAME) and ttl.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct FileSourceConfig {
+    pub path: String,
+    // Derived from the extension of the path when not set
+    pub format: Option<FileFormat>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileFormat {
+    Yaml,
+    Json,
+    Csv,
+}
+
+// Settings of a command source.
+// The command is run without a shell, and is killed when it doesn't finish within the timeout of the source.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct CommandSourceConfig {
+    pub command: String,
+    pub args: Vec<String>,
+    pub working_directory: Option<String>,
+    // Added to the environment the command inherits
+    pub env: BTreeMap<String, String>,
+}
+
+// Settings of an http source.
+// All fields are JSON pointers (RFC 6901), the fields are relative to a single entry of the records.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct HttpSourceConfig {
+    // The array of records in the response, the response itself when empty
+    pub records: String,
+    pub fields: FieldMapping,
+}
+
+// Where the fields of a record are in an entry. Fields without a pointer are left empty,
+// except the cluster name, which is the source name by default.
+// A fqdn field with an array of names results in a record per name.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct FieldMapping {
+    pub fqdn: String,
+    pub ip: String,
+    pub cluster_ip: Option<String>,
+    pub cluster_name: Option<String>,
+    pub controller: Option<String>,
+    pub kind: Option<String>,
+    pub namespace: Option<String>,
+    pub port: Option<String>,
+}
+
+impl Default for FieldMapping {
+    fn default() -> Self {
+        FieldMapping {
+            fqdn: "/fqdn".to_string(),
+            ip: "/ip".to_string(),
+            cluster_ip: None,
+            cluster_name: None,
+            controller: None,
+            kind: None,
+            namespace: None,
+            port: None,
+        }
+    }
+}
+
+// Settings of a dhcp source.
+// The fqdn of a lease is the client hostname plus the domain name of the source.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct DhcpSourceConfig {
+    pub lease_file_path: String,
+    pub format: LeaseFormat,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LeaseFormat {
+    // The dnsmasq.leases file of dnsmasq
+    #[default]
+    Dnsmasq,
+    // The dhcpd.leases file of the ISC DHCP server
+    Isc,
+}
+
+// Settings of a consul source.
+// The fqdn of a service is the service name plus the domain name of the source,
+// the ip is the service address of an instance, or the address of its node when the service has none.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ConsulConfig {
+    // Only services with this tag, all services when not set
+    pub tag: Option<String>,
+    // The datacenter of the agent when not set
+    pub datacenter: Option<String>,
+    // How long a blocking query for catalog changes waits before Consul answers anyway, at most 600
+    pub wait_in_seconds: u64,
+}
+
+impl Default for ConsulConfig {
+    fn default() -> Self {
+        ConsulConfig {
+            tag: None,
+            datacenter: None,
+            wait_in_seconds: 300,
+        }
+    }
+}
+
+// Settings of an axfr source.
+// The url of the source is the DNS server, like dns://192.168.178.2 or dns://ns1.home.lab:5353, port 53 by default.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct AxfrConfig {
+    // The zone to transfer, the domain name of the source when empty
+    pub zone: String,
+    // Signs the transfer with a TSIG key and verifies the signatures of the server
+    pub tsig: Option<TsigKey>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TsigKey {
+    // The name of the key, as configured on the server
+    pub name: String,
+    #[serde(default)]
+    pub algorithm: TsigAlgorithm,
+    // The base64 encoded secret of the key
+    pub secret: Secret,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum TsigAlgorithm {
+    #[default]
+    #[serde(rename = "hmac-sha256")]
+    HmacSha256,
+    #[serde(rename = "hmac-sha512")]
+    HmacSha512,
+}
+
+// A stream of record changes of a source, as Server-Sent Events or JSON lines, depending on the Content-Type.
+// Every event is a JSON object like {"type": "ADDED", "record": {...}}, with type ADDED, MODIFIED or DELETED.
+// The records are polled once when the stream connects, and every cycle while the stream is down.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct StreamConfig {
+    pub url: String,
+    // How long to wait for more events, before regenerating the DNS records
+    pub debounce_in_seconds: u64,
+    // How long the stream may stay silent, keep-alives included, before it is considered dropped
+    pub idle_timeout_in_seconds: u64,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        StreamConfig {
+            url: "".to_string(),
+            debounce_in_seconds: 5,
+            idle_timeout_in_seconds: 120,
+        }
+    }
+}
+
+// Which records of a source are used: those that match any include rule, or all when there are none,
+// except those that match any exclude rule.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct RecordFilters {
+    pub include: Vec<FilterRule>,
+    pub exclude: Vec<FilterRule>,
+}
+
+// A rule matches a record when all of its patterns match, fields without a pattern match anything.
+// A pattern is a glob, like test-*, or a regular expression between slashes, like /^(dev|test)-/.
+// A list of patterns matches when any of them matches.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct FilterRule {
+    pub namespace: Vec<String>,
+    pub kind: Vec<String>,
+    pub controller: Vec<String>,
+    pub cluster_name: Vec<String>,
+    pub fqdn: Vec<String>,
+}
+
+// Replaces the first match of the regular expression in the FQDN of a record.
+// The replacement may refer to the groups of the pattern, like $1 or ${name}.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct RewriteRule {
+    pub pattern: String,
+    pub replacement: String,
+}
+
+// TLS settings of an external source.
+// The client certificate and key are either PEM file paths or base64 encoded PEM, the key is PKCS#8, PKCS#1 or SEC1.
+// The CA certificates of a source replace Config.ca_cert_base64, and may contain multiple PEM certificates.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct TlsConfig {
+    pub client_cert_path: Option<String>,
+    pub client_key_path: Option<String>,
+    pub client_cert_base64: Option<String>,
+    pub client_key_base64: Option<String>,
+    pub ca_cert_base64: Option<String>,
+    pub ca_bundle_path: Option<String>,
+    // Trust the system root certificates next to the configured CA certificates, like the global CA certificate always was
+    pub trust_system_roots: bool,
+    // Disables certificate verification altogether, only meant for lab clusters
+    pub insecure_skip_verify: bool,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            client_cert_path: None,
+            client_key_path: None,
+            client_cert_base64: None,
+            client_key_base64: None,
+            ca_cert_base64: None,
+            ca_bundle_path: None,
+            trust_system_roots: true,
+            insecure_skip_verify: false,
+        }
+    }
+}
+
+// The config is logged, so never print the client key itself
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("client_cert_path", &self.client_cert_path)
+            .field("client_key_path", &self.client_key_path)
+            .field("client_cert_base64", &self.client_cert_base64)
+            .field(
+                "client_key_base64",
+                &self.client_key_base64.as_ref().map(|_| "<redacted>"),
+            )
+            .field("ca_cert_base64", &self.ca_cert_base64)
+            .field("ca_bundle_path", &self.ca_bundle_path)
+            .field("trust_system_roots", &self.trust_system_roots)
+            .field("insecure_skip_verify", &self.insecure_skip_verify)
+            .finish()
+    }
+}
+
+// Credentials sent along with every request to an external source.
+// Only one of bearer_token and basic can be set, headers can be combined with either.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub bearer_token: Option<Secret>,
+    pub basic: Option<BasicAuth>,
+    pub headers: BTreeMap<String, Secret>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BasicAuth {
+    pub username: String,
+    pub password: Secret,
+}
+
+// A reference to a secret, so the secret itself is never stored in the config file.
+// For example: {"file": "/etc/coredns-k8s-sync/token"}, {"env": "IDEC_TOKEN"} or {"credential": "idec-token"},
+// where the latter reads the systemd credential from $CREDENTIALS_DIRECTORY.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Secret {
+    File(String),
+    Env(String),
+    Credential(String),
+}
+
+// A zone file of its own, with the records whose FQDN is part of the origin
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ZoneConfig {
+    // The domain of the zone, like home.lab
+    pub origin: String,
+    pub destination_file_path: String,
+    // Static zone files included before the records, the first one contains the SOA record when soa is not set
+    #[serde(default)]
+    pub source_file_paths: Vec<String>,
+    // Generates the SOA and NS records of the zone, with a serial that increases whenever the zone changes
+    #[serde(default)]
+    pub soa: Option<SoaConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct SoaConfig {
+    // The primary name server of the zone, like ns1.home.lab
+    pub primary_name_server: String,
+    // The mailbox of the administrator of the zone, like hostmaster@home.lab
+    pub admin_email: String,
+    // The names of the NS records of the zone
+    pub name_servers: Vec<String>,
+    pub refresh_in_seconds: u64,
+    pub retry_in_seconds: u64,
+    pub expire_in_seconds: u64,
+    pub minimum_in_seconds: u64,
+}
+
+impl Default for SoaConfig {
+    fn default() -> Self {
+        SoaConfig {
+            primary_name_server: "".to_string(),
+            admin_email: "".to_string(),
+            name_servers: vec![],
+            refresh_in_seconds: 7200,
+            retry_in_seconds: 3600,
+            expire_in_seconds: 1_209_600,
+            minimum_in_seconds: 3600,
+        }
+    }
+}
+
+// How conflicting records are resolved, the policy of the first rule that matches the FQDN applies, the global policy otherwise.
+// Records conflict when they have the same name, unless one is an A and the other an AAAA record.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct DuplicatesConfig {
+    pub policy: DuplicatePolicy,
+    // Source names, the first one wins a conflict. Sources that are not listed follow in the order of external_sources.
+    pub priority: Vec<String>,
+    pub rules: Vec<DuplicateRule>,
+}
+
+// A policy for the names that match the pattern, a glob like *.kind.home.lab, or a regular expression between slashes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct DuplicateRule {
+    pub fqdn: String,
+    pub policy: DuplicatePolicy,
+    // Replaces the global priority for these names when not empty
+    pub priority: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicatePolicy {
+    // The source with the highest priority wins
+    #[default]
+    Priority,
+    // The source that published the name first keeps it, remembered in temp_storage_path
+    FirstSeen,
+    // A and AAAA records with different addresses are all kept, other conflicts are resolved by priority
+    Merge,
+    // Any conflict fails the cycle, so the zone files keep the records of the last cycle without conflicts
+    Fail,
+}
+
+// How often, and how patiently, a failed fetch of an external source is retried.
+// Only transient errors (connection failures, timeouts, 429 and 5xx responses) are retried.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct RetryPolicy {
+    // Total number of attempts, including the first one
+    pub max_attempts: u32,
+    pub base_delay_in_millis: u64,
+    pub max_delay_in_millis: u64,
+    // Randomize each delay between half and the full backoff delay
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay_in_millis: 500,
+            max_delay_in_millis: 10_000,
+            jitter: true,
+        }
+    }
 }
 
 pub fn load_config(config_file_path: String) -> std::result::Result<Config, Box<dyn Error>> {
     let config: Config = serde_json::from_str(&fs::read_to_string(config_file_path)?)?;
+    for external_source in &config.external_sources {
+        if external_source.interval_in_seconds == Some(0) {
+            return Err(format!("the interval_in_seconds of {} must be at least 1", external_source.source_name).into());
+        }
+        if external_source.tls.as_ref().is_some_and(|tls| tls.insecure_skip_verify) {
+            warn!("TLS certificate verification is DISABLED for source {} (insecure_skip_verify), never use this outside of a lab", external_source.source_name);
+        }
+        RecordFilter::new(&external_source.filters)
+            .map_err(|e| format!("invalid filters of {} - {}", external_source.source_name, e))?;
+        FqdnRewriter::new(&external_source.rewrites)
+            .map_err(|e| format!("invalid rewrites of {} - {}", external_source.source_name, e))?;
+    }
+    DuplicatePolicies::new(&config.duplicates).map_err(|e| format!("invalid duplicates - {}", e))?;
+    let priorities = config.duplicates.rules.iter().map(|rule| &rule.priority);
+    for source_name in std::iter::once(&config.duplicates.priority).chain(priorities).flatten() {
+        if !config.external_sources.iter().any(|external_source| &external_source.source_name == source_name) {
+            return Err(format!("invalid duplicates - priority source {} is not an external source", source_name).into());
+        }
+    }
+    for zone in &config.zones {
+        if zone.origin.trim_end_matches('.').is_empty() || zone.destination_file_path.is_empty() {
+            return Err(format!("zone {:?} needs an origin and a destination_file_path", zone.origin).into());
+        }
+        if let Some(soa) = &zone.soa {
+            if soa.primary_name_server.is_empty() || soa.admin_email.is_empty() {
+                return Err(format!("the soa of zone {} needs a primary_name_server and an admin_email", zone.origin).into());
+            }
+        }
+    }
     Ok(config)
 }
 
@@ -45,8 +683,8 @@ impl fmt::Display for Config {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "External Sources: {:?}\nDestination File Path: {}\nSource File Paths: {:?}\nTTL: {}\nCall Frequency: {}\nCA Cert Base64: {}\nLog Level: {}\n",
-            self.external_sources, self.destination_file_path, self.source_file_paths, self.ttl, self.call_frequency_in_minutes, self.ca_cert_base64, self.log_level
+            "External Sources: {:?}\nDestination File Path: {}\nSource File Paths: {:?}\nTTL: {}\nCall Frequency: {}\nCA Cert Base64: {}\nLog Level: {}\nFetch Timeout: {}s\nCycle Timeout: {}s\nMax Staleness: {}m\nZones: {:?}\nDuplicates: {:?}\n",
+            self.external_sources, self.destination_file_path, self.source_file_paths, self.ttl, self.call_frequency_in_minutes, self.ca_cert_base64, self.log_level, self.fetch_timeout_in_seconds, self.cycle_timeout_in_seconds, self.max_staleness_in_minutes, self.zones, self.duplicates
         )
     }
 }
@@ -54,8 +692,8 @@ impl fmt::Display for ExternalSource {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "URL: {}\nDomain Name: {}\nSource Name: {}\n",
-            self.url, self.domain_name, self.source_name
+            "Type: {:?}\nURL: {}\nDomain Name: {}\nSource Name: {}\nTimeout: {:?}\nInterval: {:?}\nJitter: {}\nRetry: {:?}\nMax Staleness: {:?}\nAuth: {:?}\nTLS: {:?}\nStream: {:?}\nFilters: {:?}\nRewrites: {:?}\n",
+            self.kind, self.url, self.domain_name, self.source_name, self.timeout_in_seconds, self.interval_in_seconds, self.jitter_in_seconds, self.retry, self.max_staleness_in_minutes, self.auth, self.tls, self.stream, self.filters, self.rewrites
         )
     }
 }
@@ -96,17 +734,208 @@ mod tests {
         assert_eq!(config.call_frequency_in_minutes, 2);
         assert_eq!(config.ca_cert_base64, "base64-encoded-ca-cert");
         assert_eq!(config.log_level, "info");
+        assert_eq!(config.fetch_timeout_in_seconds, 10);
+        assert_eq!(config.cycle_timeout_in_seconds, 60);
+        assert_eq!(config.max_staleness_in_minutes, 1440);
+        assert_eq!(config.external_sources[0].timeout_in_seconds, None);
+        assert_eq!(config.external_sources[0].retry, RetryPolicy::default());
+        assert_eq!(config.external_sources[0].auth, None);
+        assert_eq!(config.external_sources[0].kind, SourceKind::Idec);
     }
-}
 
+    #[test]
+    fn test_read_json_with_timeouts() {
+        let json = json!({
+            "external_sources": [
+                {
+                    "url": "https://api.example.com",
+                    "domain_name": "example.com",
+                    "source_name": "example",
+                    "timeout_in_seconds": 3
+                }
+            ],
+            "destination_file_path": "/var/lib/coredns/db.home.lab",
+            "temp_storage_path": "/tmp/coredns/",
+            "source_file_paths": [],
+            "ttl": 3600,
+            "call_frequency_in_minutes": 2,
+            "ca_cert_base64": "",
+            "log_level": "info",
+            "fetch_timeout_in_seconds": 5,
+            "cycle_timeout_in_seconds": 20
+        });
+
+        let config: Config = serde_json::from_value(json).unwrap();
+
+        assert_eq!(config.fetch_timeout_in_seconds, 5);
+        assert_eq!(config.cycle_timeout_in_seconds, 20);
+        assert_eq!(config.external_sources[0].timeout_in_seconds, Some(3));
+    }
+
+    #[test]
+    fn test_read_json_with_source_types() {
+        let json = json!([
+            {
+                "type": "kubernetes",
+                "url": "https://127.0.0.1:6443",
+                "domain_name": "example.com",
+                "source_name": "kind",
+                "namespace": "default",
+                "resources": ["services", "virtualservices"]
+            },
+            {
+                "type": "docker",
+                "domain_name": "home.lab",
+                "source_name": "mandarin",
+                "host_ip": "192.168.178.123"
+            }
+        ]);
+
+        let sources: Vec<ExternalSource> = serde_json::from_value(json).unwrap();
+
+        assert_eq!(
+            sources[0].kind,
+            SourceKind::Kubernetes(KubernetesConfig {
+                namespace: Some("default".to_string()),
+                resources: vec![KubernetesResource::Services, KubernetesResource::VirtualServices],
+                ..Default::default()
+            })
+        );
+        assert_eq!(sources[0].url, "https://127.0.0.1:6443");
+        assert_eq!(
+            sources[1].kind,
+            SourceKind::Docker(DockerConfig {
+                host_ip: Some("192.168.178.123".to_string()),
+                ..Default::default()
+            })
+        );
+
+        // The type is written next to the other fields, so the source reads back the same
+        let written = serde_json::to_value(&sources[1]).unwrap();
+        assert_eq!(written["type"], "docker");
+        assert_eq!(serde_json::from_value::<ExternalSource>(written).unwrap(), sources[1]);
+    }
+
+    #[test]
+    fn test_read_json_with_zones() {
+        let json = json!({
+            "external_sources": [],
+            "temp_storage_path": "/tmp/coredns/",
+            "ttl": 3600,
+            "call_frequency_in_minutes": 2,
+            "ca_cert_base64": "",
+            "log_level": "info",
+            "zones": [
+                {
+                    "origin": "home.lab",
+                    "destination_file_path": "/var/lib/coredns/db.home.lab",
+                    "source_file_paths": ["/etc/coredns/source.home.lab"]
+                },
+                {
+                    "origin": "kind.home.lab.",
+                    "destination_file_path": "/var/lib/coredns/db.kind.home.lab",
+                    "soa": {
+                        "primary_name_server": "ns1.home.lab",
+                        "admin_email": "hostmaster@home.lab",
+                        "name_servers": ["ns1.home.lab"]
+                    }
+                }
+            ]
+        });
+
+        let config: Config = serde_json::from_value(json).unwrap();
+
+        assert_eq!(config.destination_file_path, "");
+        assert_eq!(config.zones[1].soa.as_ref().unwrap().refresh_in_seconds, 7200);
+        assert_eq!(config.zone_for("app.kind.home.lab").unwrap().origin, "kind.home.lab.");
+        assert_eq!(config.zone_for("nas.home.lab").unwrap().origin, "home.lab");
+        assert_eq!(config.zone_for("home.lab").unwrap().origin, "home.lab");
+        assert_eq!(config.zone_for("app.example.com"), None);
+    }
+
+    #[test]
+    fn test_read_json_with_unknown_source_type_fails() {
+        let json = json!({
+            "type": "carrier-pigeon",
+            "domain_name": "example.com",
+            "source_name": "example"
+        });
+        assert!(serde_json::from_value::<ExternalSource>(json).is_err());
+    }
 
-// handle listening on a channel for config updates
-pub async fn handle_config_update(mut receiver: tokio::sync::mpsc::Receiver<Result<notify::Event, notify::Error>>)  {
-    info!("Listening for config updates...");
-    while let Some(_) = receiver.recv().await {
-        // reload the config
-        let config = load_config("config.json".to_string()).unwrap();
-        info!("Config updated: {}", config);
+    #[test]
+    fn test_read_json_with_unknown_source_field_fails() {
+        let json = json!({
+            "type": "docker",
+            "domain_name": "home.lab",
+            "source_name": "mandarin",
+            "hostip": "192.168.178.123"
+        });
+        let error = serde_json::from_value::<ExternalSource>(json).unwrap_err();
+        assert!(error.to_string().contains("unknown field `hostip` for source type docker"), "Unexpected error: {}", error);
+
+        // Without a type, the source is an IDEC source, which has no fields of its own
+        let json = json!({
+            "url": "https://idec.example.com/export",
+            "domain_name": "example.com",
+            "source_name": "example",
+            "interval_in_second": 60
+        });
+        let error = serde_json::from_value::<ExternalSource>(json).unwrap_err();
+        assert!(error.to_string().contains("unknown field `interval_in_second` for source type idec"), "Unexpected error: {}", error);
     }
 
+    #[test]
+    fn test_read_json_with_source_overrides() {
+        let json = json!({
+            "external_sources": [
+                {
+                    "url": "https://api.example.com",
+                    "domain_name": "example.com",
+                    "source_name": "example",
+                    "retry": {
+                        "max_attempts": 5,
+                        "jitter": false
+                    },
+                    "auth": {
+                        "basic": {
+                            "username": "coredns",
+                            "password": { "credential": "idec-password" }
+                        },
+                        "headers": {
+                            "X-Api-Key": { "env": "IDEC_API_KEY" }
+                        }
+                    }
+                }
+            ],
+            "destination_file_path": "/var/lib/coredns/db.home.lab",
+            "temp_storage_path": "/tmp/coredns/",
+            "source_file_paths": [],
+            "ttl": 3600,
+            "call_frequency_in_minutes": 2,
+            "ca_cert_base64": "",
+            "log_level": "info"
+        });
+
+        let config: Config = serde_json::from_value(json).unwrap();
+
+        assert_eq!(config.external_sources[0].retry.max_attempts, 5);
+        assert_eq!(config.external_sources[0].retry.base_delay_in_millis, 500);
+        assert!(!config.external_sources[0].retry.jitter);
+
+        let auth = config.external_sources[0].auth.clone().unwrap();
+        assert_eq!(auth.bearer_token, None);
+        assert_eq!(
+            auth.basic,
+            Some(BasicAuth {
+                username: "coredns".to_string(),
+                password: Secret::Credential("idec-password".to_string()),
+            })
+        );
+        assert_eq!(
+            auth.headers["X-Api-Key"],
+            Secret::Env("IDEC_API_KEY".to_string())
+        );
+    }
 }
+
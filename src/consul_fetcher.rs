@@ -0,0 +1,336 @@
+// consul_fetcher.rs
+use crate::auth;
+use crate::config::{ConsulConfig, ExternalSource, SourceKind};
+use crate::dns_record::DnsRecord;
+use crate::dns_record_collector::DnsRecordFetcher;
+use crate::http_client;
+use crate::retry::FetchError;
+use async_trait::async_trait;
+use log::{debug, warn};
+use reqwest::{Client, Url};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::sync::Mutex;
+use tokio::time::{sleep, Duration};
+
+// How long to wait before the next blocking query, after a failed one
+const BLOCKING_QUERY_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Fetches DNS records from the services in the catalog of a Consul agent, optionally only those with a tag.
+/// Remembers the X-Consul-Index of the catalog per source, so a blocking query can wait for the next change.
+/// Also remembers the global CA certificates of the last fetch per source, so the blocking query trusts the same CAs.
+#[derive(Default)]
+pub struct ConsulDnsRecordFetcher {
+    catalog_indexes: Mutex<HashMap<String, u64>>,
+    ca_certs_base64: Mutex<HashMap<String, String>>,
+}
+
+// An instance of a service, as listed by /v1/catalog/service/<name>
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct CatalogService {
+    node: String,
+    address: String,
+    #[serde(default)]
+    service_address: String,
+    #[serde(default)]
+    service_port: u16,
+}
+
+#[async_trait]
+impl DnsRecordFetcher for ConsulDnsRecordFetcher {
+    async fn fetch_dns_records(
+        &self,
+        source: &ExternalSource,
+        ca_cert_base64: &str,
+    ) -> Result<Vec<DnsRecord>, Box<dyn Error>> {
+        let consul = match &source.kind {
+            SourceKind::Consul(consul) => consul,
+            _ => return Err("the source is not a consul source".into()),
+        };
+        let client = http_client::build_client(source, ca_cert_base64)?;
+        self.ca_certs_base64
+            .lock()
+            .unwrap()
+            .insert(source.source_name.clone(), ca_cert_base64.to_string());
+
+        // The names of the services with their tags
+        let (services, index): (BTreeMap<String, Vec<String>>, _) =
+            get_json(&client, source, consul, &["services"], vec![]).await?;
+
+        let mut dns_records = Vec::new();
+        for (service_name, tags) in services {
+            if let Some(tag) = &consul.tag {
+                if !tags.contains(tag) {
+                    continue;
+                }
+            }
+            let query = match &consul.tag {
+                Some(tag) => vec![("tag", tag.clone())],
+                None => vec![],
+            };
+            let (instances, _): (Vec<CatalogService>, _) =
+                get_json(&client, source, consul, &["service", &service_name], query).await?;
+            dns_records.extend(instances_to_dns_records(&service_name, &instances, source));
+        }
+
+        if let Some(index) = index {
+            self.catalog_indexes
+                .lock()
+                .unwrap()
+                .insert(source.source_name.clone(), index);
+        }
+        Ok(dns_records)
+    }
+
+    async fn wait_for_change(&self, source: &ExternalSource) {
+        let consul = match &source.kind {
+            SourceKind::Consul(consul) => consul,
+            _ => return std::future::pending().await,
+        };
+        loop {
+            // Without an index of a successful fetch, the source is fetched every cycle
+            let last_index = self
+                .catalog_indexes
+                .lock()
+                .unwrap()
+                .get(&source.source_name)
+                .copied();
+            let Some(last_index) = last_index else {
+                return std::future::pending().await;
+            };
+
+            match self.blocking_query(source, consul, last_index).await {
+                Ok(Some(index)) if index != last_index => {
+                    // Also remembered when the next fetch fails, so a failing source doesn't trigger every cycle
+                    self.catalog_indexes
+                        .lock()
+                        .unwrap()
+                        .insert(source.source_name.clone(), index);
+                    return;
+                }
+                Ok(_) => debug!(
+                    "The catalog of {} did not change within {} seconds",
+                    source.source_name, consul.wait_in_seconds
+                ),
+                Err(e) => {
+                    warn!(
+                        "Blocking query for catalog changes of {} failed, retrying in {} seconds - {}",
+                        source.source_name,
+                        BLOCKING_QUERY_RETRY_DELAY.as_secs(),
+                        e
+                    );
+                    sleep(BLOCKING_QUERY_RETRY_DELAY).await;
+                }
+            }
+        }
+    }
+}
+
+impl ConsulDnsRecordFetcher {
+    // Wait until the catalog index passes the last index, or the wait time of the source passes.
+    // Returns the index of the catalog after the wait.
+    async fn blocking_query(
+        &self,
+        source: &ExternalSource,
+        consul: &ConsulConfig,
+        last_index: u64,
+    ) -> Result<Option<u64>, String> {
+        let ca_cert_base64 = self
+            .ca_certs_base64
+            .lock()
+            .unwrap()
+            .get(&source.source_name)
+            .cloned()
+            .unwrap_or_default();
+        let client =
+            http_client::build_client(source, &ca_cert_base64).map_err(|e| e.to_string())?;
+        let query = vec![
+            ("index", last_index.to_string()),
+            ("wait", format!("{}s", consul.wait_in_seconds)),
+        ];
+        let (_, index): (serde_json::Value, _) =
+            get_json(&client, source, consul, &["services"], query)
+                .await
+                .map_err(|e| e.to_string())?;
+        Ok(index)
+    }
+}
+
+// GET a /v1/catalog endpoint of the agent, returning the response and its X-Consul-Index
+async fn get_json<T: DeserializeOwned>(
+    client: &Client,
+    source: &ExternalSource,
+    consul: &ConsulConfig,
+    path: &[&str],
+    mut query: Vec<(&str, String)>,
+) -> Result<(T, Option<u64>), Box<dyn Error>> {
+    let mut url = Url::parse(&source.url)?;
+    url.path_segments_mut()
+        .map_err(|_| format!("{} can't be the base of the Consul API", source.url))?
+        .pop_if_empty()
+        .extend(["v1", "catalog"])
+        .extend(path);
+    if let Some(datacenter) = &consul.datacenter {
+        query.push(("dc", datacenter.clone()));
+    }
+
+    let mut request = client.get(url).query(&query);
+    if let Some(auth) = &source.auth {
+        request = auth::apply(request, auth)?;
+    }
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        return Err(Box::new(FetchError::from_response(&response)));
+    }
+    let index = response
+        .headers()
+        .get("X-Consul-Index")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+    Ok((response.json().await?, index))
+}
+
+// A record per distinct address of the instances of a service
+fn instances_to_dns_records(
+    service_name: &str,
+    instances: &[CatalogService],
+    source: &ExternalSource,
+) -> Vec<DnsRecord> {
+    let mut dns_records: Vec<DnsRecord> = Vec::new();
+    for instance in instances {
+        let ip = if instance.service_address.is_empty() {
+            &instance.address
+        } else {
+            &instance.service_address
+        };
+        if ip.is_empty() || dns_records.iter().any(|r| &r.ip == ip) {
+            continue;
+        }
+        dns_records.push(DnsRecord {
+            cluster_name: source.source_name.clone(),
+            controller: instance.node.clone(),
+            fqdn: format!("{}.{}", service_name.to_lowercase(), source.domain_name),
+            ip: ip.clone(),
+            kind: "ConsulService".to_string(),
+            port: match instance.service_port {
+                0 => "".to_string(),
+                port => port.to_string(),
+            },
+            ..Default::default()
+        });
+    }
+    dns_records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{record_lines, test_source, TestResponse, TestServer};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    fn consul_source(url: &str, consul: ConsulConfig) -> ExternalSource {
+        test_source(
+            "consul-dc1",
+            "service.home.lab",
+            url,
+            SourceKind::Consul(consul),
+        )
+    }
+
+    fn catalog_response(path: &str, index: u64) -> TestResponse {
+        let fixture = if path.starts_with("/v1/catalog/services") {
+            "testdata/consul/services.json"
+        } else if path.starts_with("/v1/catalog/service/web") {
+            "testdata/consul/service-web.json"
+        } else {
+            return TestResponse::json("[]").with_header("X-Consul-Index", &index.to_string());
+        };
+        TestResponse::json(&std::fs::read_to_string(fixture).unwrap())
+            .with_header("X-Consul-Index", &index.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_records_of_services_with_tag() {
+        let server = TestServer::start(|request| catalog_response(&request.path, 7)).await;
+        let source = consul_source(
+            &server.url,
+            ConsulConfig {
+                tag: Some("public".to_string()),
+                datacenter: Some("dc1".to_string()),
+                ..Default::default()
+            },
+        );
+
+        let dns_records = ConsulDnsRecordFetcher::default()
+            .fetch_dns_records(&source, "")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            record_lines(&dns_records),
+            vec![
+                "web.service.home.lab A 192.168.178.40 kind=ConsulService controller=node-1 port=8080",
+                "web.service.home.lab A 192.168.178.42 kind=ConsulService controller=node-2 port=8080",
+            ]
+        );
+        let paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/v1/catalog/services?dc=dc1",
+                "/v1/catalog/service/web?tag=public&dc=dc1"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_change_returns_when_catalog_index_changes() {
+        // The first blocking query times out without a change, the second one returns a new index
+        let index = Arc::new(AtomicU64::new(7));
+        let server_index = index.clone();
+        let server = TestServer::start(move |request| {
+            let index = if request.path.contains("index=") {
+                server_index.fetch_add(1, Ordering::SeqCst)
+            } else {
+                server_index.load(Ordering::SeqCst)
+            };
+            catalog_response(&request.path, index)
+        })
+        .await;
+        let source = consul_source(&server.url, ConsulConfig::default());
+        let fetcher = ConsulDnsRecordFetcher::default();
+        fetcher.fetch_dns_records(&source, "").await.unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), fetcher.wait_for_change(&source))
+            .await
+            .expect("The change of the catalog was not noticed");
+
+        let blocking_queries: Vec<String> = server
+            .requests()
+            .into_iter()
+            .map(|r| r.path)
+            .filter(|path| path.contains("index="))
+            .collect();
+        assert_eq!(
+            blocking_queries,
+            vec![
+                "/v1/catalog/services?index=7&wait=300s",
+                "/v1/catalog/services?index=7&wait=300s"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_change_waits_without_fetch() {
+        let source = consul_source("http://127.0.0.1:1", ConsulConfig::default());
+        let fetcher = ConsulDnsRecordFetcher::default();
+        let waited =
+            tokio::time::timeout(Duration::from_millis(100), fetcher.wait_for_change(&source))
+                .await;
+        assert!(waited.is_err());
+    }
+}
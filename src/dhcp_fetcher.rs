@@ -0,0 +1,372 @@
+// dhcp_fetcher.rs
+use crate::config::{DhcpSourceConfig, ExternalSource, LeaseFormat, SourceKind};
+use crate::dns_record::{DnsRecord, RecordType};
+use crate::dns_record_collector::DnsRecordFetcher;
+use crate::file_watcher;
+use async_trait::async_trait;
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time::{sleep, Duration};
+
+// DHCP servers write a lease file in several steps, wait for them to finish before reading it
+const DEBOUNCE_DELAY: Duration = Duration::from_secs(1);
+
+/// Fetches DNS records from the active leases in the lease file of a DHCP server, dnsmasq or ISC dhcpd.
+/// Remembers when the lease file was modified at the last fetch, so a change is noticed
+/// even when it happened before the file was watched.
+#[derive(Default)]
+pub struct DhcpDnsRecordFetcher {
+    modified_at_last_fetch: Mutex<HashMap<String, SystemTime>>,
+}
+
+#[async_trait]
+impl DnsRecordFetcher for DhcpDnsRecordFetcher {
+    async fn fetch_dns_records(
+        &self,
+        source: &ExternalSource,
+        _ca_cert_base64: &str,
+    ) -> Result<Vec<DnsRecord>, Box<dyn Error>> {
+        let dhcp = dhcp_config(source)?;
+        let path = &dhcp.lease_file_path;
+        let modified = tokio::fs::metadata(path).await?.modified()?;
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| format!("failed to read lease file {} - {}", path, e))?;
+        self.modified_at_last_fetch
+            .lock()
+            .unwrap()
+            .insert(source.source_name.clone(), modified);
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let leases = match dhcp.format {
+            LeaseFormat::Dnsmasq => parse_dnsmasq_leases(&content, now),
+            LeaseFormat::Isc => parse_isc_leases(&content, now),
+        };
+        Ok(leases_to_dns_records(&leases, source, dhcp.format))
+    }
+
+    async fn wait_for_change(&self, source: &ExternalSource) {
+        let path = match &source.kind {
+            SourceKind::Dhcp(dhcp) => dhcp.lease_file_path.clone(),
+            _ => return std::future::pending().await,
+        };
+
+        let last_modified = self
+            .modified_at_last_fetch
+            .lock()
+            .unwrap()
+            .get(&source.source_name)
+            .cloned();
+        let modified = tokio::fs::metadata(&path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        // A missing lease file is no change, the watcher notices when it is created again
+        let changed_since_fetch = matches!(
+            (last_modified, modified),
+            (Some(last_modified), Some(modified)) if modified != last_modified
+        );
+        if !changed_since_fetch {
+            if let Err(e) = file_watcher::wait_for_file_change(&path).await {
+                warn!(
+                    "Failed to watch lease file {} of {}, it is only read every cycle - {}",
+                    path, source.source_name, e
+                );
+                return std::future::pending().await;
+            }
+        }
+        sleep(DEBOUNCE_DELAY).await;
+    }
+}
+
+fn dhcp_config(source: &ExternalSource) -> Result<&DhcpSourceConfig, Box<dyn Error>> {
+    match &source.kind {
+        SourceKind::Dhcp(dhcp) => Ok(dhcp),
+        _ => Err("the source is not a dhcp source".into()),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Lease {
+    ip: String,
+    hostname: String,
+}
+
+// A dnsmasq lease is a line with the expiry time (0 never expires), MAC address or IAID, IP address, hostname and client ID.
+// The IPv6 leases follow a line with the DUID of the server.
+fn parse_dnsmasq_leases(content: &str, now: u64) -> Vec<Lease> {
+    let mut leases = Vec::new();
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 || fields[0] == "duid" {
+            continue;
+        }
+        let expires_at: u64 = match fields[0].parse() {
+            Ok(expires_at) => expires_at,
+            Err(_) => continue,
+        };
+        if expires_at != 0 && expires_at <= now {
+            continue;
+        }
+        leases.push(Lease {
+            ip: fields[2].to_string(),
+            hostname: fields[3].to_string(),
+        });
+    }
+    leases
+}
+
+// The ISC dhcpd.leases file is a log of lease declarations, a later declaration of an address replaces the earlier one.
+//   lease 192.168.178.50 {
+//     ends 4 2024/01/04 22:00:00;
+//     binding state active;
+//     client-hostname "nas";
+//   }
+fn parse_isc_leases(content: &str, now: u64) -> Vec<Lease> {
+    // The address, the end of the lease (None never ends), whether it is active and the hostname
+    let mut declarations: Vec<(String, Option<u64>, bool, String)> = Vec::new();
+    let mut current: Option<(String, Option<u64>, bool, String)> = None;
+
+    for line in content.lines() {
+        let line = line.trim();
+        if let Some(rest) = line.strip_prefix("lease ") {
+            let ip = rest.trim_end_matches('{').trim().to_string();
+            current = Some((ip, None, true, "".to_string()));
+        } else if line == "}" {
+            if let Some(declaration) = current.take() {
+                declarations.retain(|(ip, ..)| ip != &declaration.0);
+                declarations.push(declaration);
+            }
+        } else if let Some((_, ends, active, hostname)) = current.as_mut() {
+            let statement = line.trim_end_matches(';');
+            if let Some(time) = statement.strip_prefix("ends ") {
+                *ends = parse_isc_time(time);
+            } else if let Some(state) = statement.strip_prefix("binding state ") {
+                *active = state == "active";
+            } else if let Some(name) = statement.strip_prefix("client-hostname ") {
+                *hostname = name.trim_matches('"').to_string();
+            }
+        }
+    }
+
+    declarations
+        .into_iter()
+        .filter(|(_, ends, active, hostname)| {
+            *active && !hostname.is_empty() && ends.is_none_or(|ends| ends > now)
+        })
+        .map(|(ip, _, _, hostname)| Lease { ip, hostname })
+        .collect()
+}
+
+// Either `never`, `epoch <seconds>` or `<weekday> <yyyy/mm/dd> <hh:mm:ss>` in UTC, None for never
+fn parse_isc_time(time: &str) -> Option<u64> {
+    let fields: Vec<&str> = time.split_whitespace().collect();
+    match fields.as_slice() {
+        ["epoch", seconds, ..] => seconds.parse().ok(),
+        [_, date, clock, ..] => {
+            let date: Vec<i64> = date.split('/').filter_map(|n| n.parse().ok()).collect();
+            let clock: Vec<i64> = clock.split(':').filter_map(|n| n.parse().ok()).collect();
+            if date.len() != 3 || clock.len() != 3 {
+                // An unreadable end is treated as expired, rather than keeping the lease forever
+                return Some(0);
+            }
+            let days = days_from_civil(date[0], date[1], date[2]);
+            Some((days * 86_400 + clock[0] * 3_600 + clock[1] * 60 + clock[2]).max(0) as u64)
+        }
+        _ => None,
+    }
+}
+
+// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn leases_to_dns_records(
+    leases: &[Lease],
+    source: &ExternalSource,
+    format: LeaseFormat,
+) -> Vec<DnsRecord> {
+    let mut dns_records = Vec::new();
+    for lease in leases {
+        // Some clients send their fully qualified name, only the host name is used
+        let hostname = lease
+            .hostname
+            .split('.')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        let valid_hostname = !hostname.is_empty()
+            && hostname
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-');
+        if !valid_hostname {
+            debug!(
+                "Skipping lease of {}, {:?} is not a valid hostname",
+                lease.ip, lease.hostname
+            );
+            continue;
+        }
+        let record_type = match lease.ip.parse::<IpAddr>() {
+            Ok(IpAddr::V4(_)) => RecordType::A,
+            Ok(IpAddr::V6(_)) => RecordType::Aaaa,
+            Err(_) => {
+                debug!("Skipping lease of {}, it is not an IP address", lease.ip);
+                continue;
+            }
+        };
+
+        dns_records.push(DnsRecord {
+            cluster_name: source.source_name.clone(),
+            controller: match format {
+                LeaseFormat::Dnsmasq => "dnsmasq".to_string(),
+                LeaseFormat::Isc => "isc-dhcpd".to_string(),
+            },
+            fqdn: format!("{}.{}", hostname, source.domain_name),
+            ip: lease.ip.clone(),
+            kind: "DhcpLease".to_string(),
+            record_type,
+            ..Default::default()
+        });
+    }
+    dns_records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{record_lines, test_source};
+
+    // 2024-01-01 12:00:00 UTC, between the start and end of the leases in the test data
+    const NOW: u64 = 1_704_110_400;
+
+    fn dhcp_source(path: &str, format: LeaseFormat) -> ExternalSource {
+        let dhcp = DhcpSourceConfig {
+            lease_file_path: path.to_string(),
+            format,
+        };
+        test_source("lab-dhcp", "home.lab", "", SourceKind::Dhcp(dhcp))
+    }
+
+    #[test]
+    fn test_parse_dnsmasq_leases_skips_expired() {
+        let content = std::fs::read_to_string("testdata/dhcp/dnsmasq.leases").unwrap();
+        let leases = parse_dnsmasq_leases(&content, NOW);
+        let hostnames: Vec<&str> = leases.iter().map(|l| l.hostname.as_str()).collect();
+        assert_eq!(hostnames, vec!["nas", "printer", "*", "nas"]);
+    }
+
+    #[test]
+    fn test_parse_isc_leases_uses_last_declaration() {
+        let content = std::fs::read_to_string("testdata/dhcp/dhcpd.leases").unwrap();
+        let leases = parse_isc_leases(&content, NOW);
+        assert_eq!(
+            leases,
+            vec![
+                Lease {
+                    ip: "192.168.178.60".to_string(),
+                    hostname: "Pi-Hole".to_string()
+                },
+                Lease {
+                    ip: "192.168.178.62".to_string(),
+                    hostname: "switch".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_isc_time() {
+        assert_eq!(parse_isc_time("1 2024/01/01 12:00:00"), Some(NOW));
+        assert_eq!(parse_isc_time("epoch 1704110400"), Some(NOW));
+        assert_eq!(parse_isc_time("never"), None);
+    }
+
+    #[test]
+    fn test_leases_to_dns_records() {
+        let content = std::fs::read_to_string("testdata/dhcp/dnsmasq.leases").unwrap();
+        let leases = parse_dnsmasq_leases(&content, NOW);
+        let source = dhcp_source("", LeaseFormat::Dnsmasq);
+
+        let dns_records = leases_to_dns_records(&leases, &source, LeaseFormat::Dnsmasq);
+
+        assert_eq!(
+            record_lines(&dns_records),
+            vec![
+                "nas.home.lab A 192.168.178.50 kind=DhcpLease controller=dnsmasq",
+                "printer.home.lab A 192.168.178.51 kind=DhcpLease controller=dnsmasq",
+                "nas.home.lab AAAA fd00::50 kind=DhcpLease controller=dnsmasq",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_change_returns_when_lease_file_changes() {
+        let path = "testdata/test_wait_for_change_returns_when_lease_file_changes.leases";
+        std::fs::write(path, "0 52:54:00:12:34:56 192.168.178.50 nas *\n").unwrap();
+        let source = dhcp_source(path, LeaseFormat::Dnsmasq);
+        let fetcher = DhcpDnsRecordFetcher::default();
+        let dns_records = fetcher.fetch_dns_records(&source, "").await.unwrap();
+        assert_eq!(dns_records.len(), 1);
+
+        let change = fetcher.wait_for_change(&source);
+        let write = async {
+            sleep(Duration::from_millis(200)).await;
+            std::fs::write(
+                path,
+                "0 52:54:00:12:34:56 192.168.178.50 nas *\n0 52:54:00:12:34:57 192.168.178.51 printer *\n",
+            )
+            .unwrap();
+        };
+        let (_, changed) =
+            tokio::join!(write, tokio::time::timeout(Duration::from_secs(5), change));
+        assert!(
+            changed.is_ok(),
+            "The change of the lease file was not noticed"
+        );
+
+        let dns_records = fetcher.fetch_dns_records(&source, "").await.unwrap();
+        assert_eq!(dns_records.len(), 2);
+
+        // Clean up
+        std::fs::remove_file(path).unwrap();
+    }
+    #[tokio::test]
+    async fn test_wait_for_change_waits_while_lease_file_is_missing() {
+        let path = "testdata/test_wait_for_change_waits_while_lease_file_is_missing.leases";
+        std::fs::write(path, "0 52:54:00:12:34:56 192.168.178.50 nas *\n").unwrap();
+        let source = dhcp_source(path, LeaseFormat::Dnsmasq);
+        let fetcher = DhcpDnsRecordFetcher::default();
+        fetcher.fetch_dns_records(&source, "").await.unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let change = fetcher.wait_for_change(&source);
+        let write = async {
+            sleep(Duration::from_millis(1500)).await;
+            std::fs::write(path, "0 52:54:00:12:34:56 192.168.178.50 nas *\n").unwrap();
+        };
+        let started_at = std::time::Instant::now();
+        let (_, changed) =
+            tokio::join!(write, tokio::time::timeout(Duration::from_secs(5), change));
+        assert!(
+            changed.is_ok(),
+            "The creation of the lease file was not noticed"
+        );
+        assert!(
+            started_at.elapsed() >= Duration::from_millis(1500),
+            "A missing lease file was taken as a change"
+        );
+
+        // Clean up
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,8 +1,8 @@
 use std::fmt;
 // dns_record.rs
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct DnsRecord {
     #[serde(rename = "clusterIP")]
     pub cluster_ip: String,
@@ -10,16 +10,42 @@ pub struct DnsRecord {
     pub cluster_name: String,
     pub controller: String,
     pub fqdn: String,
-    #[serde(skip_deserializing)]
+    #[serde(skip)]
     pub a_record: String,
     pub ip: String,
     pub kind: String,
     pub namespace: String,
     pub port: String,
-    #[serde(skip_deserializing)]
+    // A when not set, as IDEC only exports A records
+    #[serde(rename = "type", default)]
+    pub record_type: RecordType,
+    // The TTL of the record, the TTL of the zone when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u64>,
+    #[serde(skip)]
     pub is_duplicate: bool,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum RecordType {
+    #[default]
+    A,
+    Aaaa,
+    // The ip of a CNAME record is the name it points to
+    Cname,
+}
+
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordType::A => write!(f, "A"),
+            RecordType::Aaaa => write!(f, "AAAA"),
+            RecordType::Cname => write!(f, "CNAME"),
+        }
+    }
+}
+
 impl Default for DnsRecord {
     fn default() -> Self {
         DnsRecord {
@@ -32,6 +58,8 @@ impl Default for DnsRecord {
             kind: "".to_string(),
             namespace: "".to_string(),
             port: "".to_string(),
+            record_type: RecordType::A,
+            ttl: None,
             is_duplicate: false,
         }
     }
@@ -48,13 +76,49 @@ impl fmt::Display for DnsRecord {
 }
 
 impl DnsRecord {
+    // Sets the zone file line of the record, despite the name for every record type
+    // A name outside the zone of the domain is written absolute, the collector leaves those out with a warning
     pub fn set_a_record(&mut self, domain_name: &str, padding_length: usize) {
-        let domain_to_strip = format!(".{}", domain_name);
-        let mut a_record_name = self.fqdn.replace(domain_to_strip.as_str(), "");
-        for _ in 0..(padding_length - a_record_name.len()) {
+        let mut a_record_name = relative_name(&self.fqdn, domain_name)
+            .unwrap_or_else(|| format!("{}.", self.fqdn.trim_end_matches('.')));
+        while a_record_name.len() < padding_length {
             a_record_name.push(' ');
         }
-        self.a_record = format!("{} IN A {}", a_record_name, self.ip);
+        let ttl = self.ttl.map(|ttl| format!("{} ", ttl)).unwrap_or_default();
+        let mut value = self.ip.clone();
+        // A CNAME to a fully qualified name must not be relative to the zone
+        if self.record_type == RecordType::Cname && value.contains('.') && !value.ends_with('.') {
+            value.push('.');
+        }
+        self.a_record = format!("{} {}IN {} {}", a_record_name, ttl, self.record_type, value);
+    }
+
+    // Records with the same name conflict, unless they are A and AAAA records or both of the same type, which is a duplicate
+    pub fn conflicts_with(&self, record_type: RecordType) -> bool {
+        self.record_type == record_type
+            || self.record_type == RecordType::Cname
+            || record_type == RecordType::Cname
+    }
+}
+
+// The name of the FQDN relative to the zone of the domain, @ for the apex of the zone.
+// Returns None when the FQDN is not in the zone. Names are compared case insensitive, a trailing dot is ignored.
+pub fn relative_name(fqdn: &str, domain_name: &str) -> Option<String> {
+    let fqdn = fqdn.trim_end_matches('.');
+    let domain_name = domain_name.trim_end_matches('.');
+    if domain_name.is_empty() {
+        return Some(fqdn.to_string());
+    }
+    if fqdn.eq_ignore_ascii_case(domain_name) {
+        return Some("@".to_string());
+    }
+    // Only a whole label may end where the domain starts, so notexample.com is not in example.com
+    let suffix = format!(".{}", domain_name.to_ascii_lowercase());
+    let prefix_length = fqdn.len().checked_sub(suffix.len())?;
+    if prefix_length > 0 && fqdn.to_ascii_lowercase().ends_with(&suffix) {
+        Some(fqdn[..prefix_length].to_string())
+    } else {
+        None
     }
 }
 
@@ -87,4 +151,86 @@ mod tests {
         assert_eq!(record_a.a_record, "test1      IN A 192.168.178.101");
         assert_eq!(record_b.a_record, "test2      IN A 192.168.178.102");
     }
+
+    #[test]
+    fn test_to_record_of_other_types_with_ttl() {
+        let mut record_aaaa = DnsRecord {
+            fqdn: "nas.example.com".to_string(),
+            ip: "fd00::10".to_string(),
+            record_type: RecordType::Aaaa,
+            ttl: Some(300),
+            ..Default::default()
+        };
+        record_aaaa.set_a_record("example.com", 8);
+
+        let mut record_cname = DnsRecord {
+            fqdn: "files.example.com".to_string(),
+            ip: "nas.example.com".to_string(),
+            record_type: RecordType::Cname,
+            ..Default::default()
+        };
+        record_cname.set_a_record("example.com", 8);
+
+        assert_eq!(record_aaaa.a_record, "nas      300 IN AAAA fd00::10");
+        assert_eq!(record_cname.a_record, "files    IN CNAME nas.example.com.");
+    }
+
+    #[test]
+    fn test_to_record_at_apex_and_outside_the_zone() {
+        let mut record_apex = DnsRecord {
+            fqdn: "example.com".to_string(),
+            ip: "192.168.178.100".to_string(),
+            ..Default::default()
+        };
+        record_apex.set_a_record("example.com", 6);
+
+        let mut record_outside = DnsRecord {
+            fqdn: "foo.other.org".to_string(),
+            ip: "192.168.178.100".to_string(),
+            ..Default::default()
+        };
+        record_outside.set_a_record("example.com", 6);
+
+        assert_eq!(record_apex.a_record, "@      IN A 192.168.178.100");
+        assert_eq!(
+            record_outside.a_record,
+            "foo.other.org. IN A 192.168.178.100"
+        );
+    }
+
+    #[test]
+    fn test_relative_name() {
+        assert_eq!(
+            relative_name("example.com", "example.com"),
+            Some("@".to_string())
+        );
+        assert_eq!(
+            relative_name("Example.COM.", "example.com"),
+            Some("@".to_string())
+        );
+        assert_eq!(
+            relative_name("a.b.example.com", "example.com"),
+            Some("a.b".to_string())
+        );
+        assert_eq!(
+            relative_name("a.example.com.", "example.com."),
+            Some("a".to_string())
+        );
+        // The domain in the middle of a name is not stripped
+        assert_eq!(
+            relative_name("a.example.com.other.org", "example.com"),
+            None
+        );
+        assert_eq!(relative_name("notexample.com", "example.com"), None);
+        assert_eq!(relative_name("foo.other.org", "example.com"), None);
+        assert_eq!(relative_name(".example.com", "example.com"), None);
+    }
+
+    #[test]
+    fn test_conflicts_with() {
+        let record = DnsRecord::default();
+        assert!(record.conflicts_with(RecordType::A));
+        assert!(!record.conflicts_with(RecordType::Aaaa));
+        assert!(record.conflicts_with(RecordType::Cname));
+    }
 }
@@ -1,52 +1,72 @@
 use crate::config::{Config, ExternalSource};
-use crate::dns_record::DnsRecord;
+use crate::dns_record::{relative_name, DnsRecord};
+use crate::duplicate_policy::{self, DuplicatePolicies, FirstSeen};
+use crate::fqdn_rewriter::FqdnRewriter;
+use crate::record_cache::{self, CachedRecords};
+use crate::record_filter::RecordFilter;
+use crate::retry::{self, FetchError};
 use async_trait::async_trait;
-use base64::{engine::general_purpose, Engine as _};
+use futures::future::{join_all, select_all};
 use log::{info, warn};
-use reqwest::{Certificate, Url};
+use rand::Rng;
 use std::collections::HashMap;
 use std::fmt;
-use std::time::Duration;
+use tokio::time::{sleep, timeout_at, Duration, Instant};
 
 #[async_trait]
-pub trait DnsRecordFetcher {
+pub trait DnsRecordFetcher: Send + Sync {
     async fn fetch_dns_records(
         &self,
         source: &ExternalSource,
         ca_cert_base64: &str,
     ) -> Result<Vec<DnsRecord>, Box<dyn std::error::Error>>;
-}
 
-pub struct RealDnsRecordFetcher;
+    // Wait until the records of the source may have changed since the last fetch, for sources that can tell,
+    // like a watched file. Sources that can't tell never return, they are fetched every call_frequency_in_minutes.
+    async fn wait_for_change(&self, _source: &ExternalSource) {
+        std::future::pending::<()>().await
+    }
 
-#[async_trait]
-impl DnsRecordFetcher for RealDnsRecordFetcher {
-    async fn fetch_dns_records(
+    // Fetch the DNS records, and tell whether they changed since the last fetch, for sources that can tell,
+    // like an HTTP endpoint that answers 304 Not Modified. Sources that can't tell always report fetched records.
+    async fn fetch_dns_records_outcome(
         &self,
         source: &ExternalSource,
         ca_cert_base64: &str,
-    ) -> Result<Vec<DnsRecord>, Box<dyn std::error::Error>> {
-        let cert_as_decoded_bytes = general_purpose::STANDARD.decode(ca_cert_base64).unwrap();
-
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(10))
-            .add_root_certificate(Certificate::from_pem(cert_as_decoded_bytes.as_slice())?)
-            .build()?;
+    ) -> Result<FetchOutcome, Box<dyn std::error::Error>> {
+        self.fetch_dns_records(source, ca_cert_base64)
+            .await
+            .map(FetchOutcome::Fetched)
+    }
+}
 
-        let result = client
-            .get(Url::parse(&source.url).unwrap())
-            .send()
-            .await?
-            .json()
-            .await?;
+// The records of a fetch, NotModified carries the records of the last fetch, which the source confirmed
+#[derive(Debug, Clone, PartialEq)]
+pub enum FetchOutcome {
+    Fetched(Vec<DnsRecord>),
+    NotModified(Vec<DnsRecord>),
+}
 
-        Ok(result)
+impl FetchOutcome {
+    pub fn into_records(self) -> Vec<DnsRecord> {
+        match self {
+            FetchOutcome::Fetched(dns_records) | FetchOutcome::NotModified(dns_records) => dns_records,
+        }
     }
 }
 
+// Sources due within this margin are fetched in the same batch, instead of regenerating the destination for each
+const BATCH_MARGIN: Duration = Duration::from_secs(1);
+
 pub struct DnsRecordCollector {
     config: Config,
     fetcher: Box<dyn DnsRecordFetcher>,
+    // The records of the last fetch of every source, so fetching some sources keeps the records of the others
+    records_by_source: HashMap<String, Vec<DnsRecord>>,
+    // When every source is due to be fetched again
+    next_fetches: HashMap<String, Instant>,
+    // The source that first published a name, loaded when a duplicate policy needs it
+    first_seen: Option<FirstSeen>,
 }
 
 impl fmt::Display for DnsRecordCollector {
@@ -58,38 +78,183 @@ impl fmt::Display for DnsRecordCollector {
 // TODO: make fetcher optional, so we use the real fetcher by default
 impl DnsRecordCollector {
     pub fn new(config: Config, fetcher: Box<dyn DnsRecordFetcher>) -> DnsRecordCollector {
-        DnsRecordCollector { config, fetcher }
+        DnsRecordCollector {
+            config,
+            fetcher,
+            records_by_source: HashMap::new(),
+            next_fetches: HashMap::new(),
+            first_seen: None,
+        }
     }
 
-    // For each external_source in the config, fetch the DNS records and store them in the dns_records_by_source HashMap
+    // The sources that are due to be fetched, including those due within the batch margin and those never fetched
+    pub fn due_sources(&self) -> Vec<String> {
+        self.due_sources_at(Instant::now())
+    }
+
+    fn due_sources_at(&self, now: Instant) -> Vec<String> {
+        let batch_end = now + BATCH_MARGIN;
+        self.config
+            .external_sources
+            .iter()
+            .filter(|external_source| {
+                self.next_fetches
+                    .get(&external_source.source_name)
+                    .is_none_or(|next_fetch| *next_fetch <= batch_end)
+            })
+            .map(|external_source| external_source.source_name.clone())
+            .collect()
+    }
+
+    // When the first source is due to be fetched, now when a source was never fetched
+    pub fn next_fetch(&self) -> Instant {
+        let now = Instant::now();
+        self.config
+            .external_sources
+            .iter()
+            .map(|external_source| {
+                self.next_fetches
+                    .get(&external_source.source_name)
+                    .copied()
+                    .unwrap_or(now)
+            })
+            .min()
+            .unwrap_or(now + Duration::from_secs(self.config.call_frequency_in_minutes * 60))
+    }
+
+    // The interval of the source, or call_frequency_in_minutes, plus a random jitter
+    fn fetch_interval(&self, external_source: &ExternalSource) -> Duration {
+        let interval = Duration::from_secs(
+            external_source
+                .interval_in_seconds
+                .unwrap_or(self.config.call_frequency_in_minutes * 60),
+        );
+        let max_jitter_in_millis = external_source.jitter_in_seconds * 1000;
+        if max_jitter_in_millis == 0 {
+            return interval;
+        }
+        interval + Duration::from_millis(rand::thread_rng().gen_range(0..=max_jitter_in_millis))
+    }
+
+    // Wait until any source reports that its records may have changed, returns the name of that source.
+    // Never returns when no source can tell.
+    pub async fn wait_for_change(&self) -> String {
+        if self.config.external_sources.is_empty() {
+            return std::future::pending().await;
+        }
+        let changes = self.config.external_sources.iter().map(|external_source| {
+            Box::pin(async move {
+                self.fetcher.wait_for_change(external_source).await;
+                external_source.source_name.clone()
+            })
+        });
+        let (source_name, _, _) = select_all(changes).await;
+        source_name
+    }
+
+    // Fetch the DNS records of all external sources concurrently, then process them in the configured order.
+    // The update loop only fetches the sources that are due, with collect_dns_records_of.
+    #[cfg(test)]
     pub async fn collect_dns_records(
         &mut self,
     ) -> Result<HashMap<String, Vec<DnsRecord>>, Box<dyn std::error::Error>> {
-        let mut fqds_seen = HashMap::new();
-        let mut dns_records_by_source: HashMap<String, Vec<DnsRecord>> = HashMap::new();
+        let source_names: Vec<String> = self
+            .config
+            .external_sources
+            .iter()
+            .map(|external_source| external_source.source_name.clone())
+            .collect();
+        self.fetch_sources(&source_names).await;
+        self.render_dns_records().await
+    }
 
-        for external_source in &self.config.external_sources {
-            info!("Fetching DNS records from {}", external_source.source_name);
+    // Fetch the DNS records of the given sources concurrently, then process the records of all sources.
+    // None when every source answered that its records are not modified, the destinations are up to date then.
+    pub async fn collect_dns_records_of(
+        &mut self,
+        source_names: &[String],
+    ) -> Result<Option<HashMap<String, Vec<DnsRecord>>>, Box<dyn std::error::Error>> {
+        if !self.fetch_sources(source_names).await {
+            info!("DNS records of {} are not modified, nothing to regenerate", source_names.join(", "));
+            return Ok(None);
+        }
+        self.render_dns_records().await.map(Some)
+    }
 
-            let fetch_result = self
-                .fetcher
-                .fetch_dns_records(external_source, self.config.ca_cert_base64.as_str())
-                .await;
-            if fetch_result.is_err() {
-                warn!(
-                    "Failed to fetch DNS records from {} - {}",
-                    external_source.source_name,
-                    fetch_result.err().unwrap()
-                );
-                continue;
-            }
+    // Fetch the DNS records of the given sources concurrently, and schedule their next fetch.
+    // Returns false when every source answered that its records are not modified since the last fetch.
+    async fn fetch_sources(&mut self, source_names: &[String]) -> bool {
+        let cycle_deadline =
+            Instant::now() + Duration::from_secs(self.config.cycle_timeout_in_seconds);
+        let sources: Vec<&ExternalSource> = self
+            .config
+            .external_sources
+            .iter()
+            .filter(|external_source| source_names.contains(&external_source.source_name))
+            .collect();
+        let fetches = sources
+            .iter()
+            .map(|external_source| self.fetch_with_retry(external_source, cycle_deadline));
+        let fetch_results = join_all(fetches).await;
+
+        let mut modified = fetch_results.is_empty();
+        let mut fetched_records = Vec::new();
+        for (external_source, fetch_result) in sources.into_iter().zip(fetch_results) {
+            modified |= !matches!(fetch_result, Ok(FetchOutcome::NotModified(_)));
+            let dns_records = match fetch_result {
+                // The cache already holds the records the source confirmed
+                Ok(FetchOutcome::NotModified(dns_records)) => Some(dns_records),
+                Ok(FetchOutcome::Fetched(dns_records)) => {
+                    self.cache_records(external_source, &dns_records).await;
+                    Some(dns_records)
+                }
+                Err(error) => {
+                    warn!(
+                        "Failed to fetch DNS records from {} - {}",
+                        external_source.source_name, error
+                    );
+                    self.cached_records(external_source).await
+                }
+            };
+            let next_fetch = Instant::now() + self.fetch_interval(external_source);
+            fetched_records.push((external_source.source_name.clone(), dns_records, next_fetch));
+        }
+        for (source_name, dns_records, next_fetch) in fetched_records {
+            match dns_records {
+                Some(dns_records) => self
+                    .records_by_source
+                    .insert(source_name.clone(), dns_records),
+                None => self.records_by_source.remove(&source_name),
+            };
+            self.next_fetches.insert(source_name, next_fetch);
+        }
+        modified
+    }
 
-            let mut dns_records = fetch_result.unwrap();
+    // Process the records of all sources in the configured order, the sources that were not fetched keep the records of their last fetch.
+    // Processing in configured order keeps the duplicate detection deterministic,
+    // regardless of which source responds first.
+    pub async fn render_dns_records(
+        &mut self,
+    ) -> Result<HashMap<String, Vec<DnsRecord>>, Box<dyn std::error::Error>> {
+        let mut dns_records_by_source: HashMap<String, Vec<DnsRecord>> = HashMap::new();
+        for external_source in &self.config.external_sources {
+            let mut dns_records = match self.records_by_source.get(&external_source.source_name) {
+                Some(dns_records) => {
+                    let dns_records = filter_records(external_source, dns_records);
+                    let dns_records = rewrite_records(external_source, dns_records);
+                    zone_records(&self.config, external_source, dns_records)
+                }
+                None => continue,
+            };
             dns_records.sort_by_key(|record| record.fqdn.clone());
             let mut longest_name = 0;
 
             for record in &mut dns_records {
-                let name_length = record.fqdn.len() - external_source.domain_name.len();
+                let origin = origin_of(&self.config, external_source, &record.fqdn);
+                let name_length = origin
+                    .and_then(|origin| relative_name(&record.fqdn, origin))
+                    .map_or(0, |name| name.len());
                 if name_length > longest_name {
                     longest_name = name_length;
                 }
@@ -97,12 +262,8 @@ impl DnsRecordCollector {
 
             let padding_length = longest_name + 4;
             for record in &mut dns_records {
-                record.set_a_record(external_source.domain_name.as_str(), padding_length);
-                if fqds_seen.contains_key(&record.fqdn) {
-                    record.is_duplicate = true;
-                } else {
-                    fqds_seen.insert(record.fqdn.clone(), true);
-                }
+                let origin = origin_of(&self.config, external_source, &record.fqdn);
+                record.set_a_record(origin.unwrap_or_default(), padding_length);
             }
 
             dns_records_by_source.insert(external_source.source_name.clone(), dns_records);
@@ -112,22 +273,452 @@ impl DnsRecordCollector {
                 external_source.source_name
             );
         }
+        self.mark_duplicates(&mut dns_records_by_source).await?;
         // Return the dns_records_by_source HashMap
         Ok(dns_records_by_source.clone())
     }
 
+    // Mark the records that lose a conflict as duplicate, with the duplicate policy of their name.
+    // The first seen sources are loaded once, and saved whenever they change.
+    async fn mark_duplicates(
+        &mut self,
+        dns_records_by_source: &mut HashMap<String, Vec<DnsRecord>>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // The duplicate policies are validated when the config is loaded
+        let policies = DuplicatePolicies::new(&self.config.duplicates).unwrap_or_else(|e| {
+            warn!("Ignoring the invalid duplicate policies - {}", e);
+            DuplicatePolicies::default()
+        });
+        if policies.uses_first_seen() && self.first_seen.is_none() {
+            self.first_seen = Some(self.load_first_seen().await);
+        }
+
+        let source_names: Vec<String> = self
+            .config
+            .external_sources
+            .iter()
+            .map(|external_source| external_source.source_name.clone())
+            .collect();
+        let mut first_seen = self.first_seen.clone().unwrap_or_default();
+        policies.mark_duplicates(&source_names, dns_records_by_source, &mut first_seen)?;
+
+        if policies.uses_first_seen() && self.first_seen.as_ref() != Some(&first_seen) {
+            if !self.config.temp_storage_path.is_empty() {
+                let result =
+                    duplicate_policy::save_first_seen(&self.config.temp_storage_path, &first_seen)
+                        .await;
+                if let Err(e) = result {
+                    warn!("Failed to save the first seen sources - {}", e);
+                }
+            }
+            self.first_seen = Some(first_seen);
+        }
+        Ok(())
+    }
+
+    // The first seen sources of an earlier run, none without a temp storage path
+    async fn load_first_seen(&self) -> FirstSeen {
+        if self.config.temp_storage_path.is_empty() {
+            return FirstSeen::new();
+        }
+        match duplicate_policy::load_first_seen(&self.config.temp_storage_path).await {
+            Ok(first_seen) => first_seen,
+            Err(e) => {
+                info!("No first seen sources available - {}", e);
+                FirstSeen::new()
+            }
+        }
+    }
+
+    // Persist the records of a successful fetch, so they can be used when the source fails later on.
+    // Caching is disabled when there is no temp storage path.
+    async fn cache_records(&self, external_source: &ExternalSource, dns_records: &[DnsRecord]) {
+        if self.config.temp_storage_path.is_empty() {
+            return;
+        }
+        let cached_records = CachedRecords::new(dns_records.to_vec());
+        let result = record_cache::save(
+            &self.config.temp_storage_path,
+            &external_source.source_name,
+            &cached_records,
+        )
+        .await;
+        if let Err(e) = result {
+            warn!(
+                "Failed to cache DNS records of {} - {}",
+                external_source.source_name, e
+            );
+        }
+    }
+
+    // The last known good records of a failed source, unless they are older than its max staleness
+    async fn cached_records(&self, external_source: &ExternalSource) -> Option<Vec<DnsRecord>> {
+        if self.config.temp_storage_path.is_empty() {
+            return None;
+        }
+        let cached_records =
+            match record_cache::load(&self.config.temp_storage_path, &external_source.source_name)
+                .await
+            {
+                Ok(cached_records) => cached_records,
+                Err(e) => {
+                    warn!(
+                        "No cached DNS records available for {} - {}",
+                        external_source.source_name, e
+                    );
+                    return None;
+                }
+            };
+
+        let max_staleness_in_minutes = external_source
+            .max_staleness_in_minutes
+            .unwrap_or(self.config.max_staleness_in_minutes);
+        let age_in_minutes = cached_records.age().as_secs() / 60;
+        if cached_records.age() > Duration::from_secs(max_staleness_in_minutes * 60) {
+            warn!(
+                "Dropping cached DNS records of {}, they are {}m old which exceeds the max staleness of {}m",
+                external_source.source_name, age_in_minutes, max_staleness_in_minutes
+            );
+            return None;
+        }
+
+        warn!(
+            "Using {} cached DNS records of {}, fetched {}m ago",
+            cached_records.records.len(),
+            external_source.source_name,
+            age_in_minutes
+        );
+        Some(cached_records.records)
+    }
+
+    // Fetch the DNS records of a single source, retrying transient failures according to the retry policy of the source.
+    // No retry is started when its delay would run past the cycle deadline.
+    async fn fetch_with_retry(
+        &self,
+        external_source: &ExternalSource,
+        cycle_deadline: Instant,
+    ) -> Result<FetchOutcome, Box<dyn std::error::Error>> {
+        let policy = &external_source.retry;
+        let mut attempt = 1;
+        loop {
+            info!(
+                "Fetching DNS records from {} (attempt {}/{})",
+                external_source.source_name, attempt, policy.max_attempts
+            );
+            let error = match self
+                .fetch_with_timeout(external_source, cycle_deadline)
+                .await
+            {
+                Ok(fetch_outcome) => return Ok(fetch_outcome),
+                Err(error) => error,
+            };
+            if attempt >= policy.max_attempts || !retry::is_transient(error.as_ref()) {
+                return Err(error);
+            }
+
+            let delay = retry::retry_after(error.as_ref())
+                .unwrap_or_else(|| retry::backoff_delay(policy, attempt));
+            if Instant::now() + delay >= cycle_deadline {
+                return Err(error);
+            }
+            warn!(
+                "Failed to fetch DNS records from {}, retrying in {}ms - {}",
+                external_source.source_name,
+                delay.as_millis(),
+                error
+            );
+            sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    // Fetch the DNS records of a single source, bounded by the source timeout and the cycle deadline
+    async fn fetch_with_timeout(
+        &self,
+        external_source: &ExternalSource,
+        cycle_deadline: Instant,
+    ) -> Result<FetchOutcome, Box<dyn std::error::Error>> {
+        let source_timeout = Duration::from_secs(
+            external_source
+                .timeout_in_seconds
+                .unwrap_or(self.config.fetch_timeout_in_seconds),
+        );
+        let source_deadline = Instant::now() + source_timeout;
+        let deadline = source_deadline.min(cycle_deadline);
+
+        let fetch = self
+            .fetcher
+            .fetch_dns_records_outcome(external_source, self.config.ca_cert_base64.as_str());
+        match timeout_at(deadline, fetch).await {
+            Ok(fetch_result) => fetch_result,
+            Err(_) if deadline == source_deadline => {
+                Err(Box::new(FetchError::Timeout(source_timeout)))
+            }
+            Err(_) => Err(Box::new(FetchError::CycleDeadline)),
+        }
+    }
+
+    // Fetch the sources with rewrite rules once, and list the FQDN of every record they use before and after the rewrites.
+    // Nothing is cached or scheduled, so the view can be shown without touching the destination.
+    pub async fn preview_rewrites(&self) -> Vec<RewritePreview> {
+        let cycle_deadline =
+            Instant::now() + Duration::from_secs(self.config.cycle_timeout_in_seconds);
+        let sources: Vec<&ExternalSource> = self
+            .config
+            .external_sources
+            .iter()
+            .filter(|external_source| !external_source.rewrites.is_empty())
+            .collect();
+        let fetches = sources
+            .iter()
+            .map(|external_source| self.fetch_with_retry(external_source, cycle_deadline));
+        let fetch_results = join_all(fetches).await;
+
+        let mut previews = Vec::new();
+        for (external_source, fetch_result) in sources.into_iter().zip(fetch_results) {
+            let dns_records = match fetch_result {
+                Ok(fetch_outcome) => filter_records(external_source, &fetch_outcome.into_records()),
+                Err(error) => {
+                    warn!(
+                        "Failed to fetch DNS records from {} - {}",
+                        external_source.source_name, error
+                    );
+                    continue;
+                }
+            };
+            let rewritten_records = rewrite_records(external_source, dns_records.clone());
+            for (record, rewritten_record) in dns_records.into_iter().zip(rewritten_records) {
+                previews.push(RewritePreview {
+                    source_name: external_source.source_name.clone(),
+                    before: record.fqdn,
+                    after: rewritten_record.fqdn,
+                });
+            }
+        }
+        previews
+    }
+
     // TODO: implement the merge_dns_records function where we store the canonical DNS records
     // TODO: sort the results by FQDN
     // TODO: strip the domain name from the FQDN
 }
 
+// The FQDN of a record before and after the rewrite rules of its source
+#[derive(Debug, PartialEq)]
+pub struct RewritePreview {
+    pub source_name: String,
+    pub before: String,
+    pub after: String,
+}
+
+// The records the filters of the source use, before the padding and duplicate detection
+fn filter_records(external_source: &ExternalSource, dns_records: &[DnsRecord]) -> Vec<DnsRecord> {
+    // The filters are validated when the config is loaded
+    let filter = match RecordFilter::new(&external_source.filters) {
+        Ok(filter) => filter,
+        Err(e) => {
+            warn!(
+                "Ignoring the invalid filters of {} - {}",
+                external_source.source_name, e
+            );
+            return dns_records.to_vec();
+        }
+    };
+    let used_records: Vec<DnsRecord> = dns_records
+        .iter()
+        .filter(|record| filter.matches(record))
+        .cloned()
+        .collect();
+    if used_records.len() < dns_records.len() {
+        info!(
+            "Filtered out {} of {} DNS records of {}",
+            dns_records.len() - used_records.len(),
+            dns_records.len(),
+            external_source.source_name
+        );
+    }
+    used_records
+}
+
+// The records with the rewrite rules of the source applied to their FQDN, before the padding and duplicate detection
+fn rewrite_records(
+    external_source: &ExternalSource,
+    mut dns_records: Vec<DnsRecord>,
+) -> Vec<DnsRecord> {
+    // The rewrite rules are validated when the config is loaded
+    match FqdnRewriter::new(&external_source.rewrites) {
+        Ok(rewriter) => rewriter.rewrite_records(&mut dns_records),
+        Err(e) => warn!(
+            "Ignoring the invalid rewrites of {} - {}",
+            external_source.source_name, e
+        ),
+    }
+    dns_records
+}
+
+// The origin of the zone a record is written to, the zone with the longest matching origin,
+// or the domain name of its source without zones. None when the record is outside of it.
+fn origin_of<'a>(
+    config: &'a Config,
+    external_source: &'a ExternalSource,
+    fqdn: &str,
+) -> Option<&'a str> {
+    if config.zones.is_empty() {
+        relative_name(fqdn, &external_source.domain_name)
+            .map(|_| external_source.domain_name.as_str())
+    } else {
+        config.zone_for(fqdn).map(|zone| zone.origin.as_str())
+    }
+}
+
+// The records in a zone, the others would end up under the wrong name
+fn zone_records(
+    config: &Config,
+    external_source: &ExternalSource,
+    dns_records: Vec<DnsRecord>,
+) -> Vec<DnsRecord> {
+    dns_records
+        .into_iter()
+        .filter(|record| {
+            let in_zone = origin_of(config, external_source, &record.fqdn).is_some();
+            if !in_zone && config.zones.is_empty() {
+                warn!(
+                    "Skipping {} of {}, it is outside of the zone {}",
+                    record.fqdn, external_source.source_name, external_source.domain_name
+                );
+            } else if !in_zone {
+                warn!(
+                    "Skipping {} of {}, it is outside of every zone",
+                    record.fqdn, external_source.source_name
+                );
+            }
+            in_zone
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::{
+        DuplicatePolicy, DuplicatesConfig, FilterRule, RecordFilters, RetryPolicy, RewriteRule,
+        ZoneConfig,
+    };
     use async_trait::async_trait;
+    use reqwest::StatusCode;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
 
     pub struct MockDnsRecordFetcher;
 
+    // Returns the configured records per source, after the configured delay
+    pub struct DelayedDnsRecordFetcher {
+        responses: HashMap<String, (Duration, Vec<DnsRecord>)>,
+    }
+
+    #[async_trait]
+    impl DnsRecordFetcher for DelayedDnsRecordFetcher {
+        async fn fetch_dns_records(
+            &self,
+            source: &ExternalSource,
+            _ca_cert_base64: &str,
+        ) -> Result<Vec<DnsRecord>, Box<dyn std::error::Error>> {
+            let (delay, records) = self.responses[&source.source_name].clone();
+            sleep(delay).await;
+            Ok(records)
+        }
+    }
+
+    // Returns the records of the not_modified source as not modified after the first fetch, the other sources always change
+    pub struct NotModifiedDnsRecordFetcher {
+        fetches: AtomicU32,
+    }
+
+    #[async_trait]
+    impl DnsRecordFetcher for NotModifiedDnsRecordFetcher {
+        async fn fetch_dns_records(
+            &self,
+            source: &ExternalSource,
+            _ca_cert_base64: &str,
+        ) -> Result<Vec<DnsRecord>, Box<dyn std::error::Error>> {
+            Ok(delayed_response(0, &format!("{}.example.com", source.source_name)).1)
+        }
+
+        async fn fetch_dns_records_outcome(
+            &self,
+            source: &ExternalSource,
+            ca_cert_base64: &str,
+        ) -> Result<FetchOutcome, Box<dyn std::error::Error>> {
+            let dns_records = self.fetch_dns_records(source, ca_cert_base64).await?;
+            if source.source_name == "not_modified" && self.fetches.fetch_add(1, Ordering::SeqCst) > 0 {
+                return Ok(FetchOutcome::NotModified(dns_records));
+            }
+            Ok(FetchOutcome::Fetched(dns_records))
+        }
+    }
+
+    fn delayed_source(name: &str, timeout_in_seconds: Option<u64>) -> ExternalSource {
+        ExternalSource {
+            url: format!("https://{}.example.com", name),
+            domain_name: "example.com".to_string(),
+            source_name: name.to_string(),
+            timeout_in_seconds,
+            retry: RetryPolicy {
+                max_attempts: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    // Fails with the configured error until the configured number of failures is reached
+    pub struct FlakyDnsRecordFetcher {
+        failures: u32,
+        error: FetchError,
+        attempts: Arc<AtomicU32>,
+    }
+
+    #[async_trait]
+    impl DnsRecordFetcher for FlakyDnsRecordFetcher {
+        async fn fetch_dns_records(
+            &self,
+            _source: &ExternalSource,
+            _ca_cert_base64: &str,
+        ) -> Result<Vec<DnsRecord>, Box<dyn std::error::Error>> {
+            let attempt = self.attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            if attempt <= self.failures {
+                return Err(Box::new(self.error.clone()));
+            }
+            Ok(delayed_response(0, "flaky.example.com").1)
+        }
+    }
+
+    fn flaky_config() -> Config {
+        Config {
+            external_sources: vec![ExternalSource {
+                source_name: "flaky".to_string(),
+                domain_name: "example.com".to_string(),
+                retry: RetryPolicy {
+                    max_attempts: 3,
+                    base_delay_in_millis: 10,
+                    max_delay_in_millis: 50,
+                    jitter: true,
+                },
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn delayed_response(delay_in_millis: u64, fqdn: &str) -> (Duration, Vec<DnsRecord>) {
+        let record = DnsRecord {
+            fqdn: fqdn.to_string(),
+            ip: "127.0.0.1".to_string(),
+            ..Default::default()
+        };
+        (Duration::from_millis(delay_in_millis), vec![record])
+    }
+
     #[async_trait]
     impl DnsRecordFetcher for MockDnsRecordFetcher {
         async fn fetch_dns_records(
@@ -161,14 +752,16 @@ mod tests {
                 url: "https://example.com".to_string(),
                 domain_name: "example.com".to_string(),
                 source_name: "test".to_string(),
+                ..Default::default()
             }],
-            temp_storage_path: "temp.home.lab".to_string(),
+            temp_storage_path: "".to_string(),
             destination_file_path: "destination.home.lab".to_string(),
             source_file_paths: vec!["source1.home.lab".to_string()],
             ttl: 0,
             call_frequency_in_minutes: 0,
             ca_cert_base64: "test".to_string(),
             log_level: "info".to_string(),
+            ..Default::default()
         };
         let mut collector = DnsRecordCollector::new(config, Box::new(MockDnsRecordFetcher));
 
@@ -178,4 +771,554 @@ mod tests {
         assert!(records[1].is_duplicate);
         assert!(!records[2].is_duplicate);
     }
+
+    #[tokio::test]
+    async fn test_collect_dns_records_fetches_sources_concurrently() {
+        let config = Config {
+            external_sources: vec![delayed_source("a", None), delayed_source("b", None)],
+            ..Default::default()
+        };
+        let fetcher = DelayedDnsRecordFetcher {
+            responses: HashMap::from([
+                ("a".to_string(), delayed_response(500, "a.example.com")),
+                ("b".to_string(), delayed_response(500, "b.example.com")),
+            ]),
+        };
+        let mut collector = DnsRecordCollector::new(config, Box::new(fetcher));
+
+        let started = Instant::now();
+        let records_map = collector.collect_dns_records().await.unwrap();
+        assert!(started.elapsed() < Duration::from_millis(900));
+        assert_eq!(records_map.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_collect_dns_records_skips_source_that_times_out() {
+        let config = Config {
+            external_sources: vec![delayed_source("slow", Some(1)), delayed_source("fast", None)],
+            ..Default::default()
+        };
+        let fetcher = DelayedDnsRecordFetcher {
+            responses: HashMap::from([
+                ("slow".to_string(), delayed_response(5000, "slow.example.com")),
+                ("fast".to_string(), delayed_response(10, "fast.example.com")),
+            ]),
+        };
+        let mut collector = DnsRecordCollector::new(config, Box::new(fetcher));
+
+        let records_map = collector.collect_dns_records().await.unwrap();
+        assert!(!records_map.contains_key("slow"));
+        assert_eq!(records_map["fast"][0].fqdn, "fast.example.com");
+    }
+
+    #[tokio::test]
+    async fn test_collect_dns_records_marks_duplicates_in_configured_order() {
+        let config = Config {
+            external_sources: vec![delayed_source("first", None), delayed_source("second", None)],
+            ..Default::default()
+        };
+        // The first source responds last, but should still own the record
+        let fetcher = DelayedDnsRecordFetcher {
+            responses: HashMap::from([
+                ("first".to_string(), delayed_response(300, "app.example.com")),
+                ("second".to_string(), delayed_response(10, "app.example.com")),
+            ]),
+        };
+        let mut collector = DnsRecordCollector::new(config, Box::new(fetcher));
+
+        let records_map = collector.collect_dns_records().await.unwrap();
+        assert!(!records_map["first"][0].is_duplicate);
+        assert!(records_map["second"][0].is_duplicate);
+    }
+
+    #[tokio::test]
+    async fn test_collect_dns_records_retries_transient_errors() {
+        let attempts = Arc::new(AtomicU32::new(0));
+        let fetcher = FlakyDnsRecordFetcher {
+            failures: 2,
+            error: FetchError::Status {
+                status: StatusCode::BAD_GATEWAY,
+                retry_after: None,
+            },
+            attempts: attempts.clone(),
+        };
+        let mut collector = DnsRecordCollector::new(flaky_config(), Box::new(fetcher));
+
+        let records_map = collector.collect_dns_records().await.unwrap();
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(records_map["flaky"][0].fqdn, "flaky.example.com");
+    }
+
+    #[tokio::test]
+    async fn test_collect_dns_records_does_not_retry_permanent_errors() {
+        let attempts = Arc::new(AtomicU32::new(0));
+        let fetcher = FlakyDnsRecordFetcher {
+            failures: 1,
+            error: FetchError::Status {
+                status: StatusCode::FORBIDDEN,
+                retry_after: None,
+            },
+            attempts: attempts.clone(),
+        };
+        let mut collector = DnsRecordCollector::new(flaky_config(), Box::new(fetcher));
+
+        let records_map = collector.collect_dns_records().await.unwrap();
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        assert!(!records_map.contains_key("flaky"));
+    }
+
+    #[tokio::test]
+    async fn test_collect_dns_records_gives_up_after_max_attempts() {
+        let attempts = Arc::new(AtomicU32::new(0));
+        let fetcher = FlakyDnsRecordFetcher {
+            failures: 10,
+            error: FetchError::Status {
+                status: StatusCode::TOO_MANY_REQUESTS,
+                retry_after: Some(Duration::from_millis(20)),
+            },
+            attempts: attempts.clone(),
+        };
+        let mut collector = DnsRecordCollector::new(flaky_config(), Box::new(fetcher));
+
+        let records_map = collector.collect_dns_records().await.unwrap();
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert!(!records_map.contains_key("flaky"));
+    }
+
+    #[tokio::test]
+    async fn test_collect_dns_records_caches_successful_fetches() {
+        let temp_storage_path = "testdata/";
+        let config = Config {
+            external_sources: vec![delayed_source("cached_success", None)],
+            temp_storage_path: temp_storage_path.to_string(),
+            ..Default::default()
+        };
+        let fetcher = DelayedDnsRecordFetcher {
+            responses: HashMap::from([(
+                "cached_success".to_string(),
+                delayed_response(0, "cached.example.com"),
+            )]),
+        };
+        let mut collector = DnsRecordCollector::new(config, Box::new(fetcher));
+
+        collector.collect_dns_records().await.unwrap();
+        let cached_records = record_cache::load(temp_storage_path, "cached_success")
+            .await
+            .unwrap();
+        assert_eq!(cached_records.records[0].fqdn, "cached.example.com");
+
+        // Clean up
+        let cache_file_path = record_cache::cache_file_path(temp_storage_path, "cached_success");
+        tokio::fs::remove_file(cache_file_path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_collect_dns_records_of_skips_sources_that_are_not_modified() {
+        let temp_storage_path = "testdata/";
+        let config = Config {
+            external_sources: vec![delayed_source("not_modified", None), delayed_source("modified", None)],
+            temp_storage_path: temp_storage_path.to_string(),
+            ..Default::default()
+        };
+        let fetcher = NotModifiedDnsRecordFetcher {
+            fetches: AtomicU32::new(0),
+        };
+        let mut collector = DnsRecordCollector::new(config, Box::new(fetcher));
+        let cache_file_path = record_cache::cache_file_path(temp_storage_path, "not_modified");
+        collector.collect_dns_records().await.unwrap();
+        tokio::fs::remove_file(&cache_file_path).await.unwrap();
+
+        // Nothing to regenerate, and the cache is not written again
+        let records_map = collector
+            .collect_dns_records_of(&["not_modified".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(records_map, None);
+        assert!(!std::path::Path::new(&cache_file_path).exists());
+
+        // A source that changed regenerates, the source that is not modified keeps its records
+        let records_map = collector
+            .collect_dns_records_of(&["not_modified".to_string(), "modified".to_string()])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(records_map["not_modified"][0].fqdn, "not_modified.example.com");
+        assert_eq!(records_map["modified"][0].fqdn, "modified.example.com");
+        assert!(!std::path::Path::new(&cache_file_path).exists());
+
+        // Clean up
+        let cache_file_path = record_cache::cache_file_path(temp_storage_path, "modified");
+        tokio::fs::remove_file(cache_file_path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_collect_dns_records_uses_cache_when_source_fails() {
+        let temp_storage_path = "testdata/";
+        let mut external_source = flaky_config().external_sources[0].clone();
+        external_source.source_name = "cached_fallback".to_string();
+        external_source.retry.max_attempts = 1;
+        let config = Config {
+            external_sources: vec![external_source],
+            temp_storage_path: temp_storage_path.to_string(),
+            max_staleness_in_minutes: 10,
+            ..Default::default()
+        };
+
+        let mut cached_records = CachedRecords::new(delayed_response(0, "cached.example.com").1);
+        cached_records.fetched_at -= 5 * 60;
+        record_cache::save(temp_storage_path, "cached_fallback", &cached_records)
+            .await
+            .unwrap();
+
+        let fetcher = FlakyDnsRecordFetcher {
+            failures: 1,
+            error: FetchError::Status {
+                status: StatusCode::BAD_GATEWAY,
+                retry_after: None,
+            },
+            attempts: Arc::new(AtomicU32::new(0)),
+        };
+        let mut collector = DnsRecordCollector::new(config, Box::new(fetcher));
+
+        let records_map = collector.collect_dns_records().await.unwrap();
+        assert_eq!(records_map["cached_fallback"][0].fqdn, "cached.example.com");
+
+        // Clean up
+        let cache_file_path = record_cache::cache_file_path(temp_storage_path, "cached_fallback");
+        tokio::fs::remove_file(cache_file_path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_collect_dns_records_drops_stale_cache() {
+        let temp_storage_path = "testdata/";
+        let mut external_source = flaky_config().external_sources[0].clone();
+        external_source.source_name = "cached_stale".to_string();
+        external_source.retry.max_attempts = 1;
+        external_source.max_staleness_in_minutes = Some(1);
+        let config = Config {
+            external_sources: vec![external_source],
+            temp_storage_path: temp_storage_path.to_string(),
+            ..Default::default()
+        };
+
+        let mut cached_records = CachedRecords::new(delayed_response(0, "cached.example.com").1);
+        cached_records.fetched_at -= 5 * 60;
+        record_cache::save(temp_storage_path, "cached_stale", &cached_records)
+            .await
+            .unwrap();
+
+        let fetcher = FlakyDnsRecordFetcher {
+            failures: 1,
+            error: FetchError::Status {
+                status: StatusCode::BAD_GATEWAY,
+                retry_after: None,
+            },
+            attempts: Arc::new(AtomicU32::new(0)),
+        };
+        let mut collector = DnsRecordCollector::new(config, Box::new(fetcher));
+
+        let records_map = collector.collect_dns_records().await.unwrap();
+        assert!(!records_map.contains_key("cached_stale"));
+
+        // Clean up
+        let cache_file_path = record_cache::cache_file_path(temp_storage_path, "cached_stale");
+        tokio::fs::remove_file(cache_file_path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_collect_dns_records_of_keeps_records_of_other_sources() {
+        let config = Config {
+            external_sources: vec![delayed_source("a", None), delayed_source("b", None)],
+            ..Default::default()
+        };
+        let attempts = Arc::new(AtomicU32::new(0));
+        let fetcher = FlakyDnsRecordFetcher {
+            failures: 0,
+            error: FetchError::CycleDeadline,
+            attempts: attempts.clone(),
+        };
+        let mut collector = DnsRecordCollector::new(config, Box::new(fetcher));
+        collector.collect_dns_records().await.unwrap();
+
+        let records_map = collector
+            .collect_dns_records_of(&["a".to_string()])
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(records_map.len(), 2);
+        // Both sources have the same record, b keeps the record of its last fetch, still a duplicate of a
+        assert!(!records_map["a"][0].is_duplicate);
+        assert!(records_map["b"][0].is_duplicate);
+    }
+
+    #[tokio::test]
+    async fn test_due_sources_follow_the_interval_of_each_source() {
+        let config = Config {
+            external_sources: vec![
+                ExternalSource {
+                    interval_in_seconds: Some(60),
+                    ..delayed_source("dev", None)
+                },
+                ExternalSource {
+                    interval_in_seconds: Some(3600),
+                    ..delayed_source("production", None)
+                },
+            ],
+            ..Default::default()
+        };
+        let fetcher = DelayedDnsRecordFetcher {
+            responses: HashMap::from([
+                ("dev".to_string(), delayed_response(0, "dev.example.com")),
+                (
+                    "production".to_string(),
+                    delayed_response(0, "production.example.com"),
+                ),
+            ]),
+        };
+        let mut collector = DnsRecordCollector::new(config, Box::new(fetcher));
+        assert_eq!(collector.due_sources(), vec!["dev", "production"]);
+
+        let fetched = Instant::now();
+        collector.collect_dns_records().await.unwrap();
+        let collected = Instant::now();
+
+        assert!(collector.due_sources_at(collected).is_empty());
+        assert!(collector.next_fetch() >= fetched + Duration::from_secs(60));
+        assert!(collector.next_fetch() <= collected + Duration::from_secs(60));
+        assert_eq!(collector.due_sources_at(collected + Duration::from_secs(60)), vec!["dev"]);
+        assert_eq!(collector.due_sources_at(collected + Duration::from_secs(3600)), vec!["dev", "production"]);
+    }
+
+    #[test]
+    fn test_fetch_interval_with_jitter() {
+        let external_source = ExternalSource {
+            interval_in_seconds: Some(60),
+            jitter_in_seconds: 10,
+            ..delayed_source("jittered", None)
+        };
+        let config = Config {
+            call_frequency_in_minutes: 5,
+            ..Default::default()
+        };
+        let collector = DnsRecordCollector::new(config, Box::new(MockDnsRecordFetcher));
+
+        for _ in 0..20 {
+            let interval = collector.fetch_interval(&external_source);
+            assert!(interval >= Duration::from_secs(60) && interval <= Duration::from_secs(70));
+        }
+        let external_source = ExternalSource {
+            interval_in_seconds: None,
+            jitter_in_seconds: 0,
+            ..external_source
+        };
+        assert_eq!(
+            collector.fetch_interval(&external_source),
+            Duration::from_secs(300)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_collect_dns_records_filters_before_duplicate_detection() {
+        let config = Config {
+            external_sources: vec![
+                ExternalSource {
+                    filters: RecordFilters {
+                        exclude: vec![FilterRule {
+                            fqdn: vec!["app.*".to_string()],
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    },
+                    ..delayed_source("first", None)
+                },
+                delayed_source("second", None),
+            ],
+            ..Default::default()
+        };
+        let fetcher = DelayedDnsRecordFetcher {
+            responses: HashMap::from([
+                ("first".to_string(), delayed_response(0, "app.example.com")),
+                ("second".to_string(), delayed_response(0, "app.example.com")),
+            ]),
+        };
+        let mut collector = DnsRecordCollector::new(config, Box::new(fetcher));
+
+        let records_map = collector.collect_dns_records().await.unwrap();
+
+        // The filtered record of the first source doesn't make the record of the second source a duplicate
+        assert!(records_map["first"].is_empty());
+        assert!(!records_map["second"][0].is_duplicate);
+    }
+
+    #[tokio::test]
+    async fn test_collect_dns_records_rewrites_before_duplicate_detection() {
+        let config = Config {
+            external_sources: vec![
+                delayed_source("first", None),
+                ExternalSource {
+                    rewrites: vec![RewriteRule {
+                        pattern: r"\.kind\.local$".to_string(),
+                        replacement: ".example.com".to_string(),
+                    }],
+                    ..delayed_source("second", None)
+                },
+            ],
+            ..Default::default()
+        };
+        let fetcher = DelayedDnsRecordFetcher {
+            responses: HashMap::from([
+                ("first".to_string(), delayed_response(0, "app.example.com")),
+                ("second".to_string(), delayed_response(0, "app.kind.local")),
+            ]),
+        };
+        let mut collector = DnsRecordCollector::new(config, Box::new(fetcher));
+
+        let previews = collector.preview_rewrites().await;
+        let records_map = collector.collect_dns_records().await.unwrap();
+
+        assert_eq!(
+            previews,
+            vec![RewritePreview {
+                source_name: "second".to_string(),
+                before: "app.kind.local".to_string(),
+                after: "app.example.com".to_string(),
+            }]
+        );
+        assert_eq!(records_map["second"][0].fqdn, "app.example.com");
+        assert!(records_map["second"][0].a_record.starts_with("app "));
+        assert!(records_map["second"][0].is_duplicate);
+    }
+
+    #[tokio::test]
+    async fn test_collect_dns_records_skips_records_outside_the_zone() {
+        let config = Config {
+            external_sources: vec![delayed_source("first", None)],
+            ..Default::default()
+        };
+        let mut response = delayed_response(0, "example.com");
+        response.1.extend(delayed_response(0, "app.example.com").1);
+        response
+            .1
+            .extend(delayed_response(0, "app.example.com.other.org").1);
+        let fetcher = DelayedDnsRecordFetcher {
+            responses: HashMap::from([("first".to_string(), response)]),
+        };
+        let mut collector = DnsRecordCollector::new(config, Box::new(fetcher));
+
+        let records_map = collector.collect_dns_records().await.unwrap();
+
+        let a_records: Vec<&str> = records_map["first"]
+            .iter()
+            .map(|r| r.a_record.as_str())
+            .collect();
+        assert_eq!(
+            a_records,
+            vec!["app     IN A 127.0.0.1", "@       IN A 127.0.0.1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_collect_dns_records_routes_records_to_longest_matching_zone() {
+        let zone = |origin: &str| ZoneConfig {
+            origin: origin.to_string(),
+            destination_file_path: format!("/var/lib/coredns/db.{}", origin),
+            ..Default::default()
+        };
+        let config = Config {
+            external_sources: vec![delayed_source("first", None)],
+            zones: vec![zone("example.com"), zone("kind.example.com")],
+            ..Default::default()
+        };
+        let mut response = delayed_response(0, "app.kind.example.com");
+        response.1.extend(delayed_response(0, "nas.example.com").1);
+        response.1.extend(delayed_response(0, "app.other.org").1);
+        let fetcher = DelayedDnsRecordFetcher {
+            responses: HashMap::from([("first".to_string(), response)]),
+        };
+        let mut collector = DnsRecordCollector::new(config, Box::new(fetcher));
+
+        let records_map = collector.collect_dns_records().await.unwrap();
+
+        let a_records: Vec<&str> = records_map["first"]
+            .iter()
+            .map(|r| r.a_record.as_str())
+            .collect();
+        assert_eq!(
+            a_records,
+            vec!["app     IN A 127.0.0.1", "nas     IN A 127.0.0.1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_collect_dns_records_keeps_first_seen_source_across_runs() {
+        let temp_storage_path = "testdata/first_seen/";
+        tokio::fs::create_dir_all(temp_storage_path).await.unwrap();
+        let config = Config {
+            external_sources: vec![
+                delayed_source("first", None),
+                delayed_source("second", None),
+            ],
+            temp_storage_path: temp_storage_path.to_string(),
+            duplicates: DuplicatesConfig {
+                policy: DuplicatePolicy::FirstSeen,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let fetcher = DelayedDnsRecordFetcher {
+            responses: HashMap::from([
+                ("first".to_string(), delayed_response(0, "one.example.com")),
+                ("second".to_string(), delayed_response(0, "app.example.com")),
+            ]),
+        };
+        let mut collector = DnsRecordCollector::new(config.clone(), Box::new(fetcher));
+        collector.collect_dns_records().await.unwrap();
+
+        // After a restart, the first source publishes the name as well, but the second one saw it first
+        let fetcher = DelayedDnsRecordFetcher {
+            responses: HashMap::from([
+                ("first".to_string(), delayed_response(0, "app.example.com")),
+                ("second".to_string(), delayed_response(0, "app.example.com")),
+            ]),
+        };
+        let mut collector = DnsRecordCollector::new(config, Box::new(fetcher));
+        let records_map = collector.collect_dns_records().await.unwrap();
+
+        assert!(records_map["first"][0].is_duplicate);
+        assert!(!records_map["second"][0].is_duplicate);
+
+        // Clean up
+        tokio::fs::remove_dir_all(temp_storage_path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_collect_dns_records_fails_on_conflict_with_fail_policy() {
+        let config = Config {
+            external_sources: vec![
+                delayed_source("first", None),
+                delayed_source("second", None),
+            ],
+            duplicates: DuplicatesConfig {
+                policy: DuplicatePolicy::Fail,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let fetcher = DelayedDnsRecordFetcher {
+            responses: HashMap::from([
+                ("first".to_string(), delayed_response(0, "app.example.com")),
+                ("second".to_string(), delayed_response(0, "app.example.com")),
+            ]),
+        };
+        let mut collector = DnsRecordCollector::new(config, Box::new(fetcher));
+
+        let result = collector.collect_dns_records().await;
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "A app.example.com of second conflicts with a record of first"
+        );
+    }
 }
@@ -0,0 +1,321 @@
+// docker_fetcher.rs
+use crate::auth;
+use crate::config::{DockerConfig, ExternalSource, SourceKind};
+use crate::dns_record::DnsRecord;
+use crate::dns_record_collector::DnsRecordFetcher;
+use crate::http_client;
+use crate::retry::FetchError;
+use async_trait::async_trait;
+use log::{debug, warn};
+use reqwest::{StatusCode, Url};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+const DEFAULT_DOCKER_HOST: &str = "unix:///var/run/docker.sock";
+const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
+const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+
+/// Fetches DNS records from the running containers of a Docker Engine.
+/// A DNS record is created per hostname in the hostname label of a container, pointing to the Docker host.
+/// This replaces hand written zone files for Compose hosted services.
+#[derive(Default)]
+pub struct DockerDnsRecordFetcher;
+
+#[async_trait]
+impl DnsRecordFetcher for DockerDnsRecordFetcher {
+    async fn fetch_dns_records(
+        &self,
+        source: &ExternalSource,
+        ca_cert_base64: &str,
+    ) -> Result<Vec<DnsRecord>, Box<dyn Error>> {
+        let docker = match &source.kind {
+            SourceKind::Docker(docker) => docker,
+            _ => return Err("the source is not a docker source".into()),
+        };
+        let body = get(source, ca_cert_base64, "/containers/json").await?;
+        let containers: Vec<Container> = serde_json::from_str(&body).map_err(|e| {
+            format!(
+                "failed to parse the containers of the Docker Engine - {}",
+                e
+            )
+        })?;
+        Ok(containers_to_dns_records(&containers, source, docker))
+    }
+}
+
+// GET a path of the Docker Engine API, over a unix socket or over TCP
+async fn get(
+    source: &ExternalSource,
+    ca_cert_base64: &str,
+    path: &str,
+) -> Result<String, Box<dyn Error>> {
+    let docker_host = if source.url.is_empty() {
+        DEFAULT_DOCKER_HOST
+    } else {
+        &source.url
+    };
+
+    if let Some(socket_path) = docker_host.strip_prefix("unix://") {
+        return get_over_unix_socket(socket_path, path).await;
+    }
+
+    let server = match docker_host.strip_prefix("tcp://") {
+        Some(address) => format!("http://{}", address),
+        None => docker_host.to_string(),
+    };
+    let client = http_client::build_client(source, ca_cert_base64)?;
+    let mut request = client.get(Url::parse(&format!(
+        "{}{}",
+        server.trim_end_matches('/'),
+        path
+    ))?);
+    if let Some(auth) = &source.auth {
+        request = auth::apply(request, auth)?;
+    }
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        return Err(Box::new(FetchError::from_response(&response)));
+    }
+    Ok(response.text().await?)
+}
+
+// reqwest can't connect to a unix socket, but the Docker Engine API only needs a plain HTTP/1.0 request:
+// the response is not chunked and ends when the connection is closed
+async fn get_over_unix_socket(socket_path: &str, path: &str) -> Result<String, Box<dyn Error>> {
+    let mut stream = UnixStream::connect(socket_path)
+        .await
+        .map_err(|e| format!("failed to connect to Docker socket {} - {}", socket_path, e))?;
+    let request = format!("GET {} HTTP/1.0\r\nHost: docker\r\n\r\n", path);
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    let response = String::from_utf8(response)?;
+
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or("the Docker Engine sent an incomplete response")?;
+    let status = head
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or("the Docker Engine sent an invalid status line")?;
+    let status = StatusCode::from_u16(status)?;
+    if !status.is_success() {
+        return Err(Box::new(FetchError::Status {
+            status,
+            retry_after: None,
+        }));
+    }
+    Ok(body.to_string())
+}
+
+// Only the fields of the container list of the Docker Engine API needed for DNS records
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, rename_all = "PascalCase")]
+pub struct Container {
+    pub names: Vec<String>,
+    pub labels: BTreeMap<String, String>,
+    pub ports: Vec<ContainerPort>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct ContainerPort {
+    #[serde(rename = "IP")]
+    pub ip: Option<String>,
+    #[serde(rename = "PublicPort")]
+    pub public_port: Option<u16>,
+    #[serde(rename = "Type")]
+    pub port_type: String,
+}
+
+impl Container {
+    fn name(&self) -> String {
+        self.names
+            .first()
+            .map(|name| name.trim_start_matches('/').to_string())
+            .unwrap_or_default()
+    }
+
+    // The first TCP port published on the host
+    fn published_port(&self) -> Option<&ContainerPort> {
+        self.ports
+            .iter()
+            .find(|port| port.public_port.is_some() && port.port_type == "tcp")
+    }
+}
+
+fn containers_to_dns_records(
+    containers: &[Container],
+    source: &ExternalSource,
+    docker: &DockerConfig,
+) -> Vec<DnsRecord> {
+    let mut dns_records = Vec::new();
+    for container in containers {
+        let hostnames: Vec<String> = match container.labels.get(&docker.hostname_label) {
+            Some(hostnames) => hostnames
+                .split(',')
+                .map(|hostname| hostname.trim().trim_end_matches('.').to_string())
+                .filter(|hostname| !hostname.is_empty())
+                .collect(),
+            None => {
+                debug!(
+                    "Skipping container {}, it has no {} label",
+                    container.name(),
+                    docker.hostname_label
+                );
+                continue;
+            }
+        };
+
+        let published_port = container.published_port();
+        // A port published on all interfaces doesn't tell the address of the host
+        let published_ip = published_port
+            .and_then(|port| port.ip.clone())
+            .filter(|ip| ip != "0.0.0.0" && ip != "::");
+        let ip = match docker.host_ip.clone().or(published_ip) {
+            Some(ip) => ip,
+            None => {
+                warn!(
+                    "Skipping container {}, set host_ip for source {} or publish a port on a specific host IP",
+                    container.name(),
+                    source.source_name
+                );
+                continue;
+            }
+        };
+
+        for hostname in hostnames {
+            dns_records.push(DnsRecord {
+                cluster_name: source.source_name.clone(),
+                controller: container
+                    .labels
+                    .get(COMPOSE_SERVICE_LABEL)
+                    .cloned()
+                    .unwrap_or_else(|| container.name()),
+                fqdn: hostname,
+                ip: ip.clone(),
+                kind: "Container".to_string(),
+                namespace: container
+                    .labels
+                    .get(COMPOSE_PROJECT_LABEL)
+                    .cloned()
+                    .unwrap_or_default(),
+                port: published_port
+                    .and_then(|port| port.public_port)
+                    .map(|port| port.to_string())
+                    .unwrap_or_default(),
+                ..Default::default()
+            });
+        }
+    }
+    dns_records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{record_lines, test_source, TestResponse, TestServer};
+
+    fn docker_source(url: &str, host_ip: Option<&str>) -> ExternalSource {
+        let docker = DockerConfig {
+            host_ip: host_ip.map(|ip| ip.to_string()),
+            ..Default::default()
+        };
+        test_source("mandarin", "home.lab", url, SourceKind::Docker(docker))
+    }
+
+    async fn docker_engine(socket_path: &str) -> TestServer {
+        let containers = std::fs::read_to_string("testdata/docker/containers.json").unwrap();
+        TestServer::start_unix(socket_path, move |request| {
+            if request.path == "/containers/json" {
+                TestResponse::json(&containers)
+            } else {
+                TestResponse::status(404)
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_fetch_containers_over_unix_socket() {
+        let server = docker_engine("testdata/test_fetch_containers_over_unix_socket.sock").await;
+        let source = docker_source(&server.url, Some("192.168.178.123"));
+
+        let dns_records = DockerDnsRecordFetcher
+            .fetch_dns_records(&source, "")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            record_lines(&dns_records),
+            vec![
+                "portainer.home.lab A 192.168.178.123 kind=Container controller=portainer port=9443",
+                "harbor.home.lab A 192.168.178.123 kind=Container namespace=harbor controller=proxy port=443",
+                "registry.home.lab A 192.168.178.123 kind=Container namespace=harbor controller=proxy port=443",
+            ]
+        );
+
+        // Clean up
+        std::fs::remove_file("testdata/test_fetch_containers_over_unix_socket.sock").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fetch_containers_uses_published_host_ip() {
+        let server =
+            docker_engine("testdata/test_fetch_containers_uses_published_host_ip.sock").await;
+        let source = docker_source(&server.url, None);
+
+        let dns_records = DockerDnsRecordFetcher
+            .fetch_dns_records(&source, "")
+            .await
+            .unwrap();
+
+        // Only harbor publishes its port on a specific IP, portainer on all interfaces
+        assert_eq!(dns_records.len(), 2);
+        assert!(dns_records.iter().all(|r| r.ip == "192.168.178.12"));
+
+        // Clean up
+        std::fs::remove_file("testdata/test_fetch_containers_uses_published_host_ip.sock").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fetch_containers_over_tcp() {
+        let containers = std::fs::read_to_string("testdata/docker/containers.json").unwrap();
+        let server = TestServer::start(move |_| TestResponse::json(&containers)).await;
+        let url = server.url.replace("http://", "tcp://");
+        let source = docker_source(&url, Some("192.168.178.123"));
+
+        let dns_records = DockerDnsRecordFetcher
+            .fetch_dns_records(&source, "")
+            .await
+            .unwrap();
+
+        assert_eq!(dns_records.len(), 3);
+        assert_eq!(server.requests()[0].path, "/containers/json");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_containers_fails_on_error_status() {
+        let socket_path = "testdata/test_fetch_containers_fails_on_error_status.sock";
+        let server = TestServer::start_unix(socket_path, |_| TestResponse::status(500)).await;
+        let source = docker_source(&server.url, None);
+
+        let error = DockerDnsRecordFetcher
+            .fetch_dns_records(&source, "")
+            .await
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("500"),
+            "Unexpected error: {}",
+            error
+        );
+
+        // Clean up
+        std::fs::remove_file(socket_path).unwrap();
+    }
+}
@@ -0,0 +1,396 @@
+// duplicate_policy.rs
+use crate::config::{DuplicatePolicy, DuplicatesConfig};
+use crate::dns_record::{DnsRecord, RecordType};
+use crate::record_filter::compile_pattern;
+use log::info;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io;
+
+/// The source that first published a name, per name with the first_seen policy
+pub type FirstSeen = BTreeMap<String, String>;
+
+/// The compiled duplicate policies of the config
+#[derive(Default)]
+pub struct DuplicatePolicies {
+    policy: DuplicatePolicy,
+    priority: Vec<String>,
+    rules: Vec<(Regex, DuplicatePolicy, Vec<String>)>,
+}
+
+// A record that is kept, that later records of the same name may conflict with
+struct KeptRecord {
+    record_type: RecordType,
+    ip: String,
+    source_index: usize,
+}
+
+impl DuplicatePolicies {
+    /// Compile the duplicate policies
+    ///
+    /// # Arguments
+    /// * `duplicates` - The global policy and the rules per name pattern
+    ///
+    /// # Returns
+    /// * `Result<DuplicatePolicies, Box<dyn Error>>` - The policies, or an error when a pattern is invalid
+    ///
+    pub fn new(duplicates: &DuplicatesConfig) -> Result<DuplicatePolicies, Box<dyn Error>> {
+        let rules = duplicates
+            .rules
+            .iter()
+            .map(|rule| {
+                Ok((
+                    compile_pattern(&rule.fqdn)?,
+                    rule.policy,
+                    rule.priority.clone(),
+                ))
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?;
+        Ok(DuplicatePolicies {
+            policy: duplicates.policy,
+            priority: duplicates.priority.clone(),
+            rules,
+        })
+    }
+
+    /// Whether any name may use the first_seen policy, which needs the persisted first seen sources
+    pub fn uses_first_seen(&self) -> bool {
+        self.policy == DuplicatePolicy::FirstSeen
+            || self
+                .rules
+                .iter()
+                .any(|(_, policy, _)| *policy == DuplicatePolicy::FirstSeen)
+    }
+
+    // The policy and source priority of the first rule that matches the FQDN, or the global ones
+    fn policy_for(&self, fqdn: &str) -> (DuplicatePolicy, &[String]) {
+        match self
+            .rules
+            .iter()
+            .find(|(pattern, _, _)| pattern.is_match(fqdn))
+        {
+            Some((_, policy, priority)) if !priority.is_empty() => (*policy, priority),
+            Some((_, policy, _)) => (*policy, &self.priority),
+            None => (self.policy, &self.priority),
+        }
+    }
+
+    /// Mark the records that lose a conflict as duplicate, according to the policy of their name.
+    /// The winner of a conflict is the first seen source with first_seen, then the source with the highest priority,
+    /// then the first source in the order of the source names. Records of the same source keep their order.
+    ///
+    /// # Arguments
+    /// * `source_names` - The names of all sources, in the order of external_sources
+    /// * `dns_records_by_source` - The records per source that has records this cycle, without any duplicates marked yet
+    /// * `first_seen` - The first seen source per name, updated with the winners of the names with first_seen
+    ///
+    /// # Returns
+    /// * `Result<(), Box<dyn Error>>` - An error when a name with the fail policy has conflicting records
+    ///
+    pub fn mark_duplicates(
+        &self,
+        source_names: &[String],
+        dns_records_by_source: &mut HashMap<String, Vec<DnsRecord>>,
+        first_seen: &mut FirstSeen,
+    ) -> Result<(), Box<dyn Error>> {
+        // The records per name, as source and record index
+        let mut candidates_by_fqdn: BTreeMap<String, Vec<(usize, usize)>> = BTreeMap::new();
+        for (source_index, source_name) in source_names.iter().enumerate() {
+            for (record_index, record) in dns_records_by_source
+                .get(source_name)
+                .into_iter()
+                .flatten()
+                .enumerate()
+            {
+                candidates_by_fqdn
+                    .entry(record.fqdn.clone())
+                    .or_default()
+                    .push((source_index, record_index));
+            }
+        }
+        // A name stays with its first seen source while that source has no records, like when its fetch fails.
+        // A name nobody publishes anymore, or of a source that is gone, is up for grabs again.
+        first_seen.retain(|fqdn, source_name| {
+            source_names.contains(source_name)
+                && (candidates_by_fqdn.contains_key(fqdn)
+                    || !dns_records_by_source.contains_key(source_name))
+        });
+
+        for (fqdn, mut candidates) in candidates_by_fqdn {
+            let (policy, priority) = self.policy_for(&fqdn);
+            let first_seen_source = match policy {
+                DuplicatePolicy::FirstSeen => first_seen.get(&fqdn).cloned(),
+                _ => None,
+            };
+            // A stable sort, so the records of a source keep their order
+            candidates.sort_by_key(|(source_index, _)| {
+                let source_name = &source_names[*source_index];
+                (
+                    first_seen_source.as_ref() != Some(source_name),
+                    priority
+                        .iter()
+                        .position(|name| name == source_name)
+                        .unwrap_or(priority.len()),
+                    *source_index,
+                )
+            });
+            let first_seen_source_is_absent = first_seen_source
+                .as_ref()
+                .is_some_and(|source_name| !dns_records_by_source.contains_key(source_name));
+            if policy == DuplicatePolicy::FirstSeen && !first_seen_source_is_absent {
+                first_seen.insert(fqdn.clone(), source_names[candidates[0].0].clone());
+            }
+
+            let mut kept_records: Vec<KeptRecord> = Vec::new();
+            for (source_index, record_index) in candidates {
+                let source_name = &source_names[source_index];
+                let record = &mut dns_records_by_source.get_mut(source_name).unwrap()[record_index];
+                let conflicts: Vec<&KeptRecord> = kept_records
+                    .iter()
+                    .filter(|kept| record.conflicts_with(kept.record_type))
+                    .collect();
+                // Merged records are served together, like the A records of a round robin
+                let merged = policy == DuplicatePolicy::Merge
+                    && record.record_type != RecordType::Cname
+                    && conflicts
+                        .iter()
+                        .all(|kept| kept.record_type == record.record_type && kept.ip != record.ip);
+                if conflicts.is_empty() || merged {
+                    kept_records.push(KeptRecord {
+                        record_type: record.record_type,
+                        ip: record.ip.clone(),
+                        source_index,
+                    });
+                    continue;
+                }
+                if policy == DuplicatePolicy::Fail {
+                    return Err(format!(
+                        "{} {} of {} conflicts with a record of {}",
+                        record.record_type,
+                        fqdn,
+                        source_name,
+                        source_names[conflicts[0].source_index]
+                    )
+                    .into());
+                }
+                record.is_duplicate = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The file with the first seen sources lives in the temporary storage, next to the cached records
+pub fn first_seen_file_path(temp_storage_path: &str) -> String {
+    temp_storage_path.to_string() + "first_seen.json"
+}
+
+/// Read the first seen sources from the temporary storage
+///
+/// # Arguments
+/// * `temp_storage_path` - A string containing the path of the temporary storage
+///
+/// # Returns
+/// * `io::Result<FirstSeen>` - The first seen source per name, or an error when there is no (valid) file
+///
+pub async fn load_first_seen(temp_storage_path: &str) -> io::Result<FirstSeen> {
+    let content = tokio::fs::read_to_string(first_seen_file_path(temp_storage_path)).await?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// Write the first seen sources to the temporary storage
+///
+/// # Arguments
+/// * `temp_storage_path` - A string containing the path of the temporary storage
+/// * `first_seen` - The first seen source per name
+///
+/// # Returns
+/// * `io::Result<()>` - A result indicating success or failure
+///
+pub async fn save_first_seen(temp_storage_path: &str, first_seen: &FirstSeen) -> io::Result<()> {
+    let first_seen_file_path = first_seen_file_path(temp_storage_path);
+    tokio::fs::write(&first_seen_file_path, serde_json::to_string(first_seen)?).await?;
+    info!(
+        "Saved the first seen sources of {} names in {}",
+        first_seen.len(),
+        first_seen_file_path
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DuplicateRule;
+
+    fn record(fqdn: &str, record_type: RecordType, ip: &str) -> DnsRecord {
+        DnsRecord {
+            fqdn: fqdn.to_string(),
+            record_type,
+            ip: ip.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn source_names() -> Vec<String> {
+        vec!["kind".to_string(), "prod".to_string()]
+    }
+
+    fn records_by_source(
+        kind: Vec<DnsRecord>,
+        prod: Vec<DnsRecord>,
+    ) -> HashMap<String, Vec<DnsRecord>> {
+        HashMap::from([("kind".to_string(), kind), ("prod".to_string(), prod)])
+    }
+
+    fn duplicates(records: &[DnsRecord]) -> Vec<bool> {
+        records.iter().map(|r| r.is_duplicate).collect()
+    }
+
+    #[test]
+    fn test_priority_wins_over_source_order() {
+        let policies = DuplicatePolicies::new(&DuplicatesConfig {
+            priority: vec!["prod".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        let mut dns_records = records_by_source(
+            vec![record("app.home.lab", RecordType::A, "10.0.0.1")],
+            vec![record("app.home.lab", RecordType::A, "10.0.0.2")],
+        );
+
+        policies
+            .mark_duplicates(&source_names(), &mut dns_records, &mut FirstSeen::new())
+            .unwrap();
+
+        assert_eq!(duplicates(&dns_records["kind"]), vec![true]);
+        assert_eq!(duplicates(&dns_records["prod"]), vec![false]);
+    }
+
+    #[test]
+    fn test_merge_keeps_different_addresses() {
+        let policies = DuplicatePolicies::new(&DuplicatesConfig {
+            rules: vec![DuplicateRule {
+                fqdn: "*.home.lab".to_string(),
+                policy: DuplicatePolicy::Merge,
+                ..Default::default()
+            }],
+            ..Default::default()
+        })
+        .unwrap();
+        let mut dns_records = records_by_source(
+            vec![
+                record("app.home.lab", RecordType::A, "10.0.0.1"),
+                record("web.home.lab", RecordType::A, "10.0.0.3"),
+            ],
+            vec![
+                record("app.home.lab", RecordType::A, "10.0.0.2"),
+                record("app.home.lab", RecordType::A, "10.0.0.1"),
+                record("web.home.lab", RecordType::Cname, "app.home.lab"),
+            ],
+        );
+
+        policies
+            .mark_duplicates(&source_names(), &mut dns_records, &mut FirstSeen::new())
+            .unwrap();
+
+        assert_eq!(duplicates(&dns_records["kind"]), vec![false, false]);
+        // The same address is a duplicate, and a CNAME can't be merged with an A record
+        assert_eq!(duplicates(&dns_records["prod"]), vec![false, true, true]);
+    }
+
+    #[test]
+    fn test_fail_on_conflict() {
+        let policies = DuplicatePolicies::new(&DuplicatesConfig {
+            policy: DuplicatePolicy::Fail,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut dns_records = records_by_source(
+            vec![record("app.home.lab", RecordType::A, "10.0.0.1")],
+            vec![
+                record("app.home.lab", RecordType::Aaaa, "fd00::1"),
+                record("app.home.lab", RecordType::A, "10.0.0.2"),
+            ],
+        );
+
+        let result =
+            policies.mark_duplicates(&source_names(), &mut dns_records, &mut FirstSeen::new());
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "A app.home.lab of prod conflicts with a record of kind"
+        );
+    }
+
+    #[test]
+    fn test_first_seen_source_keeps_the_name() {
+        let policies = DuplicatePolicies::new(&DuplicatesConfig {
+            policy: DuplicatePolicy::FirstSeen,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut first_seen = FirstSeen::from([
+            ("app.home.lab".to_string(), "prod".to_string()),
+            ("gone.home.lab".to_string(), "prod".to_string()),
+        ]);
+        let mut dns_records = records_by_source(
+            vec![
+                record("app.home.lab", RecordType::A, "10.0.0.1"),
+                record("new.home.lab", RecordType::A, "10.0.0.3"),
+            ],
+            vec![
+                record("app.home.lab", RecordType::A, "10.0.0.2"),
+                record("new.home.lab", RecordType::A, "10.0.0.4"),
+            ],
+        );
+
+        policies
+            .mark_duplicates(&source_names(), &mut dns_records, &mut first_seen)
+            .unwrap();
+
+        assert_eq!(duplicates(&dns_records["kind"]), vec![true, false]);
+        assert_eq!(duplicates(&dns_records["prod"]), vec![false, true]);
+        assert_eq!(
+            first_seen,
+            FirstSeen::from([
+                ("app.home.lab".to_string(), "prod".to_string()),
+                ("new.home.lab".to_string(), "kind".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_first_seen_source_without_records_keeps_the_name() {
+        let policies = DuplicatePolicies::new(&DuplicatesConfig {
+            policy: DuplicatePolicy::FirstSeen,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut first_seen = FirstSeen::from([
+            ("app.home.lab".to_string(), "prod".to_string()),
+            ("db.home.lab".to_string(), "prod".to_string()),
+            ("web.home.lab".to_string(), "staging".to_string()),
+        ]);
+        // The fetch of prod failed, without cached records
+        let mut dns_records = HashMap::from([(
+            "kind".to_string(),
+            vec![record("app.home.lab", RecordType::A, "10.0.0.1")],
+        )]);
+
+        policies
+            .mark_duplicates(&source_names(), &mut dns_records, &mut first_seen)
+            .unwrap();
+
+        assert_eq!(duplicates(&dns_records["kind"]), vec![false]);
+        // The source staging is not configured anymore
+        assert_eq!(
+            first_seen,
+            FirstSeen::from([
+                ("app.home.lab".to_string(), "prod".to_string()),
+                ("db.home.lab".to_string(), "prod".to_string()),
+            ])
+        );
+    }
+}
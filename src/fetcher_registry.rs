@@ -0,0 +1,152 @@
+// fetcher_registry.rs
+use crate::axfr_fetcher::AxfrDnsRecordFetcher;
+use crate::command_fetcher::CommandDnsRecordFetcher;
+use crate::config::ExternalSource;
+use crate::consul_fetcher::ConsulDnsRecordFetcher;
+use crate::dhcp_fetcher::DhcpDnsRecordFetcher;
+use crate::dns_record::DnsRecord;
+use crate::dns_record_collector::{DnsRecordFetcher, FetchOutcome};
+use crate::docker_fetcher::DockerDnsRecordFetcher;
+use crate::file_fetcher::FileDnsRecordFetcher;
+use crate::http_fetcher::HttpDnsRecordFetcher;
+use crate::idec_fetcher::IdecDnsRecordFetcher;
+use crate::kubernetes_fetcher::KubernetesDnsRecordFetcher;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::error::Error;
+
+/// Dispatches every source to the fetcher registered for its type.
+/// A new kind of source only needs a `SourceKind` variant and a registered fetcher,
+/// the DnsRecordCollector fetches all sources through the registry.
+pub struct FetcherRegistry {
+    fetchers: HashMap<&'static str, Box<dyn DnsRecordFetcher>>,
+}
+
+impl FetcherRegistry {
+    /// A registry without any fetchers
+    pub fn new() -> FetcherRegistry {
+        FetcherRegistry {
+            fetchers: HashMap::new(),
+        }
+    }
+
+    /// Register the fetcher for a source type, replacing the fetcher registered before
+    ///
+    /// # Arguments
+    /// * `source_type` - The type of the source, as returned by `SourceKind::name`
+    /// * `fetcher` - The fetcher for sources of this type
+    ///
+    pub fn register(&mut self, source_type: &'static str, fetcher: Box<dyn DnsRecordFetcher>) {
+        self.fetchers.insert(source_type, fetcher);
+    }
+}
+
+impl Default for FetcherRegistry {
+    /// A registry with the fetchers of all built-in source types
+    fn default() -> Self {
+        let mut registry = FetcherRegistry::new();
+        registry.register("idec", Box::new(IdecDnsRecordFetcher::new()));
+        registry.register("kubernetes", Box::new(KubernetesDnsRecordFetcher));
+        registry.register("docker", Box::new(DockerDnsRecordFetcher));
+        registry.register("file", Box::new(FileDnsRecordFetcher));
+        registry.register("command", Box::new(CommandDnsRecordFetcher));
+        registry.register("http", Box::new(HttpDnsRecordFetcher));
+        registry.register("dhcp", Box::new(DhcpDnsRecordFetcher::default()));
+        registry.register("consul", Box::new(ConsulDnsRecordFetcher::default()));
+        registry.register("axfr", Box::new(AxfrDnsRecordFetcher));
+        registry
+    }
+}
+
+#[async_trait]
+impl DnsRecordFetcher for FetcherRegistry {
+    async fn fetch_dns_records(
+        &self,
+        source: &ExternalSource,
+        ca_cert_base64: &str,
+    ) -> Result<Vec<DnsRecord>, Box<dyn Error>> {
+        self.fetch_dns_records_outcome(source, ca_cert_base64)
+            .await
+            .map(FetchOutcome::into_records)
+    }
+
+    async fn fetch_dns_records_outcome(
+        &self,
+        source: &ExternalSource,
+        ca_cert_base64: &str,
+    ) -> Result<FetchOutcome, Box<dyn Error>> {
+        let source_type = source.kind.name();
+        let fetcher = self
+            .fetchers
+            .get(source_type)
+            .ok_or_else(|| format!("no fetcher registered for source type {}", source_type))?;
+        fetcher
+            .fetch_dns_records_outcome(source, ca_cert_base64)
+            .await
+    }
+
+    async fn wait_for_change(&self, source: &ExternalSource) {
+        match self.fetchers.get(source.kind.name()) {
+            Some(fetcher) => fetcher.wait_for_change(source).await,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DockerConfig, SourceKind};
+
+    struct NamedDnsRecordFetcher(&'static str);
+
+    #[async_trait]
+    impl DnsRecordFetcher for NamedDnsRecordFetcher {
+        async fn fetch_dns_records(
+            &self,
+            _source: &ExternalSource,
+            _ca_cert_base64: &str,
+        ) -> Result<Vec<DnsRecord>, Box<dyn Error>> {
+            Ok(vec![DnsRecord {
+                controller: self.0.to_string(),
+                ..Default::default()
+            }])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registry_dispatches_on_source_type() {
+        let mut registry = FetcherRegistry::new();
+        registry.register("idec", Box::new(NamedDnsRecordFetcher("idec")));
+        registry.register("docker", Box::new(NamedDnsRecordFetcher("docker")));
+
+        let docker_source = ExternalSource {
+            kind: SourceKind::Docker(DockerConfig::default()),
+            ..Default::default()
+        };
+        let dns_records = registry
+            .fetch_dns_records(&docker_source, "")
+            .await
+            .unwrap();
+        assert_eq!(dns_records[0].controller, "docker");
+
+        let dns_records = registry
+            .fetch_dns_records(&ExternalSource::default(), "")
+            .await
+            .unwrap();
+        assert_eq!(dns_records[0].controller, "idec");
+    }
+
+    #[tokio::test]
+    async fn test_registry_fails_on_unregistered_source_type() {
+        let registry = FetcherRegistry::new();
+        let error = registry
+            .fetch_dns_records(&ExternalSource::default(), "")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "no fetcher registered for source type idec"
+        );
+    }
+}
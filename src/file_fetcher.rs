@@ -0,0 +1,203 @@
+// file_fetcher.rs
+use crate::config::{ExternalSource, FileFormat, FileSourceConfig, SourceKind};
+use crate::dns_record::{DnsRecord, RecordType};
+use crate::dns_record_collector::DnsRecordFetcher;
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::error::Error;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+/// Fetches static DNS records from a YAML, JSON or CSV file.
+/// Records for hosts outside of any cluster, like a NAS or a router, go through the same validation,
+/// sorting and duplicate detection as the records of the other sources.
+#[derive(Default)]
+pub struct FileDnsRecordFetcher;
+
+#[async_trait]
+impl DnsRecordFetcher for FileDnsRecordFetcher {
+    async fn fetch_dns_records(
+        &self,
+        source: &ExternalSource,
+        _ca_cert_base64: &str,
+    ) -> Result<Vec<DnsRecord>, Box<dyn Error>> {
+        let file = match &source.kind {
+            SourceKind::File(file) => file,
+            _ => return Err("the source is not a file source".into()),
+        };
+        let content = tokio::fs::read_to_string(&file.path)
+            .await
+            .map_err(|e| format!("failed to read {} - {}", file.path, e))?;
+        let static_records = parse_static_records(&content, format(file)?)
+            .map_err(|e| format!("failed to parse {} - {}", file.path, e))?;
+
+        let mut dns_records = Vec::new();
+        for (index, static_record) in static_records.into_iter().enumerate() {
+            let dns_record = static_record
+                .into_dns_record(source)
+                .map_err(|e| format!("invalid record {} in {} - {}", index + 1, file.path, e))?;
+            dns_records.push(dns_record);
+        }
+        Ok(dns_records)
+    }
+}
+
+// An entry of the file, the same fields for every format
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+struct StaticRecord {
+    fqdn: String,
+    ip: String,
+    #[serde(rename = "type")]
+    record_type: Option<RecordType>,
+    ttl: Option<u64>,
+}
+
+impl StaticRecord {
+    fn into_dns_record(self, source: &ExternalSource) -> Result<DnsRecord, Box<dyn Error>> {
+        let fqdn = self.fqdn.trim().trim_end_matches('.').to_string();
+        if fqdn.is_empty() {
+            return Err("the fqdn is empty".into());
+        }
+        let record_type = self.record_type.unwrap_or_default();
+        let valid = match record_type {
+            RecordType::A => self.ip.parse::<Ipv4Addr>().is_ok(),
+            RecordType::Aaaa => self.ip.parse::<Ipv6Addr>().is_ok(),
+            RecordType::Cname => !self.ip.trim().is_empty(),
+        };
+        if !valid {
+            return Err(format!(
+                "{} is not a valid value for the {} record of {}",
+                self.ip, record_type, fqdn
+            )
+            .into());
+        }
+
+        Ok(DnsRecord {
+            cluster_name: source.source_name.clone(),
+            fqdn,
+            ip: self.ip.trim().to_string(),
+            kind: "Static".to_string(),
+            record_type,
+            ttl: self.ttl,
+            ..Default::default()
+        })
+    }
+}
+
+fn format(file: &FileSourceConfig) -> Result<FileFormat, Box<dyn Error>> {
+    if let Some(format) = file.format {
+        return Ok(format);
+    }
+    let extension = Path::new(&file.path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    match extension.as_str() {
+        "yaml" | "yml" => Ok(FileFormat::Yaml),
+        "json" => Ok(FileFormat::Json),
+        "csv" => Ok(FileFormat::Csv),
+        _ => Err(format!(
+            "can't derive the format of {} from its extension, set the format of the source",
+            file.path
+        )
+        .into()),
+    }
+}
+
+// A YAML or JSON file is a list of records, a CSV file has a header row with the field names
+fn parse_static_records(
+    content: &str,
+    format: FileFormat,
+) -> Result<Vec<StaticRecord>, Box<dyn Error>> {
+    match format {
+        FileFormat::Yaml => Ok(serde_yaml::from_str(content)?),
+        FileFormat::Json => Ok(serde_json::from_str(content)?),
+        FileFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .comment(Some(b'#'))
+                .from_reader(content.as_bytes());
+            let mut static_records = Vec::new();
+            for static_record in reader.deserialize() {
+                static_records.push(static_record?);
+            }
+            Ok(static_records)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{record_lines, test_source};
+
+    fn file_source(path: &str) -> ExternalSource {
+        let file = FileSourceConfig {
+            path: path.to_string(),
+            format: None,
+        };
+        test_source("static", "home.lab", "", SourceKind::File(file))
+    }
+
+    #[tokio::test]
+    async fn test_fetch_static_records_in_every_format() {
+        let expected = vec![
+            "nas.home.lab A 192.168.178.10 kind=Static",
+            "nas.home.lab AAAA fd00::10 kind=Static ttl=300",
+            "files.home.lab CNAME nas.home.lab kind=Static",
+            "router.home.lab A 192.168.178.1 kind=Static ttl=3600",
+        ];
+        for path in [
+            "testdata/static/records.yaml",
+            "testdata/static/records.json",
+            "testdata/static/records.csv",
+        ] {
+            let dns_records = FileDnsRecordFetcher
+                .fetch_dns_records(&file_source(path), "")
+                .await
+                .unwrap();
+            assert_eq!(
+                record_lines(&dns_records),
+                expected,
+                "Unexpected records in {}",
+                path
+            );
+            assert!(dns_records.iter().all(|r| r.cluster_name == "static"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_static_records_fails_on_invalid_ip() {
+        let path = "testdata/test_fetch_static_records_fails_on_invalid_ip.csv";
+        std::fs::write(path, "fqdn,ip,type,ttl\nnas.home.lab,fd00::10,A,\n").unwrap();
+
+        let error = FileDnsRecordFetcher
+            .fetch_dns_records(&file_source(path), "")
+            .await
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("invalid record 1"),
+            "Unexpected error: {}",
+            error
+        );
+
+        // Clean up
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_format_without_known_extension_fails() {
+        let file = FileSourceConfig {
+            path: "records.txt".to_string(),
+            format: None,
+        };
+        assert!(format(&file).is_err());
+
+        let file = FileSourceConfig {
+            format: Some(FileFormat::Yaml),
+            ..file
+        };
+        assert_eq!(format(&file).unwrap(), FileFormat::Yaml);
+    }
+}
@@ -1,10 +1,11 @@
 
-use notify::RecommendedWatcher;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use tokio::sync::mpsc::Sender;
 
 
 // Receive a list of paths to watch and a channel to send the events to
-pub async fn create_watcher(tx: Sender<()>) -> notify::Result<(RecommendedWatcher)> {
+#[allow(dead_code)]
+pub async fn create_watcher(tx: Sender<()>) -> notify::Result<RecommendedWatcher> {
   
   let watcher = notify::recommended_watcher( move|res| {
     let tx = tx.clone();
@@ -21,3 +22,25 @@ pub async fn create_watcher(tx: Sender<()>) -> notify::Result<(RecommendedWatche
 
   Ok(watcher)
 }
+
+// Wait until the file at the path is created, modified or replaced.
+// The directory of the file is watched, as tools like dnsmasq replace the file instead of writing to it.
+pub async fn wait_for_file_change(path: &str) -> notify::Result<()> {
+  let path = std::path::absolute(path)?;
+  let directory = path.parent().unwrap_or(std::path::Path::new("/")).to_path_buf();
+
+  let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+  let watched_path = path.clone();
+  let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+    if let Ok(event) = res {
+      if !event.kind.is_access() && event.paths.iter().any(|path| path == &watched_path) {
+        // The receiver only needs to know something changed, a full channel already says so
+        let _ = tx.try_send(());
+      }
+    }
+  })?;
+  watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+
+  rx.recv().await;
+  Ok(())
+}
@@ -1,10 +1,12 @@
 use io::ErrorKind;
 use std::io;
 use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::fs::OpenOptions;
 use tokio::io::AsyncReadExt;
 use ErrorKind::NotFound;
 
+use crate::config::{SoaConfig, ZoneConfig};
 use crate::dns_record::DnsRecord;
 use log::{info, warn};
 
@@ -46,22 +48,7 @@ pub async fn merge_source_files(
 
     // Add the source files content
     for source_file_path in source_file_paths {
-        if !Path::new(&source_file_path).exists() {
-            warn!("Source file {} not found", source_file_path);
-            let error = io::Error::new(NotFound, "File not found");
-            let result = Err(error);
-            return result;
-        } else {
-            info!("Source file {} found", source_file_path);
-            let content = read_content_from_source_file(&source_file_path).await?;
-            destination_file_content.push('\n');
-            destination_file_content.push_str("; Source File: ");
-            destination_file_content.push_str(&source_file_path);
-            destination_file_content.push('\n');
-            destination_file_content.push_str(&content);
-            destination_file_content.push('\n');
-            destination_file_content.push('\n');
-        }
+        append_source_file(&mut destination_file_content, &source_file_path, None).await?;
     }
 
     info!("Writing to destination file: {}", destination_file_path);
@@ -72,6 +59,156 @@ pub async fn merge_source_files(
     Ok(())
 }
 
+/// Write the zone file of a zone, with its generated SOA and NS records when configured,
+/// followed by its static source files and the files with its records.
+/// The file is not rewritten when nothing but the serial would change.
+///
+/// # Arguments
+/// * `zone` - The zone, with its origin and destination file
+/// * `ttl` - The TTL of the SOA and NS records
+/// * `record_file_paths` - The files with the records of the zone, with names relative to the origin
+///
+/// # Returns
+/// * `io::Result<()>` - A result indicating success or failure
+///
+pub async fn write_zone_file(
+    zone: &ZoneConfig,
+    ttl: u64,
+    record_file_paths: Vec<String>,
+) -> io::Result<()> {
+    let origin = absolute_name(&zone.origin);
+    let mut header = String::new();
+    header.push_str("; This file was generated by the coredns-k8s-sync module\n");
+    header.push_str("; Do not edit this file manually\n");
+    header.push_str(&format!("; Zone: {}\n", origin));
+
+    let mut records_content = String::new();
+    for source_file_path in &zone.source_file_paths {
+        append_source_file(&mut records_content, source_file_path, None).await?;
+    }
+    // The static files may change the origin, the records are relative to the origin of the zone
+    for record_file_path in &record_file_paths {
+        append_source_file(&mut records_content, record_file_path, Some(&origin)).await?;
+    }
+    let zone_file_content = |serial: u32| match &zone.soa {
+        Some(soa) => format!(
+            "{}{}{}",
+            header,
+            soa_records(&origin, soa, ttl, serial),
+            records_content
+        ),
+        None => format!("{}{}", header, records_content),
+    };
+
+    let existing_content = tokio::fs::read_to_string(&zone.destination_file_path)
+        .await
+        .ok();
+    let previous_serial = existing_content.as_deref().and_then(serial_of);
+    if existing_content == Some(zone_file_content(previous_serial.unwrap_or_default())) {
+        info!(
+            "Zone {} in file {} is unchanged, skipping write",
+            origin, zone.destination_file_path
+        );
+        return Ok(());
+    }
+
+    // A serial that increases with every change, the current time unless the previous serial is already past it
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as u32);
+    let serial = previous_serial.map_or(now, |serial| now.max(serial.wrapping_add(1)));
+
+    info!(
+        "Writing zone {} to file: {}",
+        origin, zone.destination_file_path
+    );
+    tokio::fs::write(&zone.destination_file_path, zone_file_content(serial)).await
+}
+
+// The SOA and NS records of a zone, in the layout of examples/source.home.lab
+fn soa_records(origin: &str, soa: &SoaConfig, ttl: u64, serial: u32) -> String {
+    let mut content = String::new();
+    content.push('\n');
+    content.push_str(&format!("$ORIGIN {}\n", origin));
+    content.push_str(&format!(
+        "@       {} IN SOA {} {} (\n",
+        ttl,
+        absolute_name(&soa.primary_name_server),
+        mailbox_name(&soa.admin_email)
+    ));
+    for (value, name) in [
+        (serial as u64, "serial"),
+        (soa.refresh_in_seconds, "refresh"),
+        (soa.retry_in_seconds, "retry"),
+        (soa.expire_in_seconds, "expire"),
+        (soa.minimum_in_seconds, "minimum"),
+    ] {
+        content.push_str(&format!(
+            "                                {:<10} ; {}\n",
+            value, name
+        ));
+    }
+    content.push_str("                                )\n");
+    content.push('\n');
+    for name_server in &soa.name_servers {
+        content.push_str(&format!(
+            "        {} IN NS {}\n",
+            ttl,
+            absolute_name(name_server)
+        ));
+    }
+    content
+}
+
+// Append the content of a source file, after the origin the records of the file are relative to when given
+async fn append_source_file(
+    destination_file_content: &mut String,
+    source_file_path: &str,
+    origin: Option<&str>,
+) -> io::Result<()> {
+    if !Path::new(source_file_path).exists() {
+        warn!("Source file {} not found", source_file_path);
+        let error = io::Error::new(NotFound, "File not found");
+        return Err(error);
+    }
+    info!("Source file {} found", source_file_path);
+    let content = read_content_from_source_file(source_file_path).await?;
+    destination_file_content.push('\n');
+    destination_file_content.push_str("; Source File: ");
+    destination_file_content.push_str(source_file_path);
+    destination_file_content.push('\n');
+    if let Some(origin) = origin {
+        destination_file_content.push_str(&format!("$ORIGIN {}\n", origin));
+    }
+    destination_file_content.push_str(&content);
+    destination_file_content.push('\n');
+    destination_file_content.push('\n');
+    Ok(())
+}
+
+// The name with a trailing dot, so it is not relative to the origin
+fn absolute_name(name: &str) -> String {
+    format!("{}.", name.trim_end_matches('.'))
+}
+
+// The mailbox of an email address as a domain name, hostmaster@home.lab becomes hostmaster.home.lab.
+fn mailbox_name(email: &str) -> String {
+    match email.split_once('@') {
+        Some((local_part, domain)) => {
+            absolute_name(&format!("{}.{}", local_part.replace('.', "\\."), domain))
+        }
+        None => absolute_name(email),
+    }
+}
+
+// The serial of the SOA record written by write_zone_file
+fn serial_of(zone_file_content: &str) -> Option<u32> {
+    zone_file_content
+        .lines()
+        .find_map(|line| line.trim().strip_suffix("; serial"))
+        .and_then(|serial| serial.trim().parse().ok())
+}
+
 async fn read_content_from_source_file(source_file_path: &str) -> io::Result<String> {
     info!("Opening source file: {}", source_file_path);
     let mut source_file = OpenOptions::new().read(true).open(source_file_path).await?;
@@ -81,7 +218,8 @@ async fn read_content_from_source_file(source_file_path: &str) -> io::Result<Str
     Ok(source_file_content)
 }
 
-/// Write DNSRecords to a file, one record per line, one file per source
+/// Write DNSRecords to a file, one record per line, one file per source.
+/// The file is not rewritten when its content would stay the same.
 ///
 /// # Arguments
 /// * `dns_records` - A slice of DnsRecord
@@ -128,6 +266,17 @@ pub async fn write_dns_records_to_file(
     // Add a newline at the end of the file
     destination_file_content.push('\n');
 
+    // Leave the file untouched when nothing changed, so its modification time keeps meaning something
+    if let Ok(existing_content) = tokio::fs::read_to_string(destination_file_path).await {
+        if existing_content == destination_file_content {
+            info!(
+                "DNS records in file {} are unchanged, skipping write",
+                destination_file_path
+            );
+            return Ok(records_written);
+        }
+    }
+
     let file_write_result = tokio::fs::write(destination_file_path, destination_file_content).await;
     match file_write_result {
         Ok(_) => {
@@ -421,4 +570,91 @@ mod tests {
         // Clean up
         tokio::fs::remove_file(destination_file_path).await.unwrap();
     }
+
+    #[tokio::test]
+    async fn test_write_dns_records_to_file_skips_unchanged_content() {
+        let destination_file_path =
+            "testdata/test_write_dns_records_to_file_skips_unchanged_content";
+        let source_name = "test_source";
+
+        let mut dns_records = generate_test_dns_records().await;
+        write_dns_records_to_file(
+            dns_records.as_mut_slice(),
+            destination_file_path,
+            source_name,
+        )
+        .await
+        .unwrap();
+        let first_modified = std::fs::metadata(destination_file_path)
+            .unwrap()
+            .modified()
+            .unwrap();
+
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let mut dns_records = generate_test_dns_records().await;
+        let result = write_dns_records_to_file(
+            dns_records.as_mut_slice(),
+            destination_file_path,
+            source_name,
+        )
+        .await;
+        assert_eq!(result.unwrap(), 6);
+        let second_modified = std::fs::metadata(destination_file_path)
+            .unwrap()
+            .modified()
+            .unwrap();
+        assert_eq!(first_modified, second_modified);
+
+        // Clean up
+        tokio::fs::remove_file(destination_file_path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_write_zone_file_with_soa() {
+        let destination_file_path = "testdata/test_write_zone_file_with_soa";
+        let records_file_path = "testdata/test_write_zone_file_with_soa.records";
+        let _ = tokio::fs::remove_file(destination_file_path).await;
+        let mut dns_records = generate_test_dns_records().await;
+        write_dns_records_to_file(dns_records.as_mut_slice(), records_file_path, "kind")
+            .await
+            .unwrap();
+        let zone = ZoneConfig {
+            origin: "example.com".to_string(),
+            destination_file_path: destination_file_path.to_string(),
+            source_file_paths: vec![],
+            soa: Some(SoaConfig {
+                primary_name_server: "ns1.example.com".to_string(),
+                admin_email: "host.master@example.com".to_string(),
+                name_servers: vec!["ns1.example.com".to_string()],
+                ..Default::default()
+            }),
+        };
+
+        write_zone_file(&zone, 3600, vec![records_file_path.to_string()])
+            .await
+            .unwrap();
+        let content = read_to_string(destination_file_path).unwrap();
+        let serial = serial_of(&content).unwrap();
+
+        assert!(content.contains(
+            "$ORIGIN example.com.\n@       3600 IN SOA ns1.example.com. host\\.master.example.com. ("
+        ));
+        assert!(content.contains("        3600 IN NS ns1.example.com.\n"));
+        assert!(content.contains("$ORIGIN example.com.\n; Source: kind\na     IN A 127.0.0.2\n"));
+
+        // An unchanged zone keeps its serial, a changed zone gets a higher one
+        write_zone_file(&zone, 3600, vec![records_file_path.to_string()])
+            .await
+            .unwrap();
+        assert_eq!(read_to_string(destination_file_path).unwrap(), content);
+        write_zone_file(&zone, 300, vec![records_file_path.to_string()])
+            .await
+            .unwrap();
+        let changed_content = read_to_string(destination_file_path).unwrap();
+        assert!(serial_of(&changed_content).unwrap() > serial);
+
+        // Clean up
+        tokio::fs::remove_file(destination_file_path).await.unwrap();
+        tokio::fs::remove_file(records_file_path).await.unwrap();
+    }
 }
@@ -0,0 +1,93 @@
+// fqdn_rewriter.rs
+use crate::config::RewriteRule;
+use crate::dns_record::DnsRecord;
+use regex::Regex;
+use std::error::Error;
+
+/// The compiled rewrite rules of a source, applied in order to the FQDN of every record
+pub struct FqdnRewriter {
+    rules: Vec<(Regex, String)>,
+}
+
+impl FqdnRewriter {
+    /// Compile the rewrite rules of a source
+    ///
+    /// # Arguments
+    /// * `rules` - The rewrite rules of the source, in the order they are applied
+    ///
+    /// # Returns
+    /// * `Result<FqdnRewriter, Box<dyn Error>>` - The rewriter, or an error when a pattern is invalid
+    ///
+    pub fn new(rules: &[RewriteRule]) -> Result<FqdnRewriter, Box<dyn Error>> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                Regex::new(&rule.pattern)
+                    .map(|pattern| (pattern, rule.replacement.clone()))
+                    .map_err(|e| format!("invalid pattern {} - {}", rule.pattern, e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(FqdnRewriter { rules })
+    }
+
+    /// The FQDN after all rules, every rule applies to the outcome of the rule before it
+    pub fn rewrite(&self, fqdn: &str) -> String {
+        self.rules
+            .iter()
+            .fold(fqdn.to_string(), |fqdn, (pattern, replacement)| {
+                pattern.replace(&fqdn, replacement.as_str()).into_owned()
+            })
+    }
+
+    /// Rewrite the FQDN of every record
+    pub fn rewrite_records(&self, dns_records: &mut [DnsRecord]) {
+        if self.rules.is_empty() {
+            return;
+        }
+        for record in dns_records {
+            record.fqdn = self.rewrite(&record.fqdn);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, replacement: &str) -> RewriteRule {
+        RewriteRule {
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_rewrite_suffix() {
+        let rewriter = FqdnRewriter::new(&[rule(r"\.kind\.local$", ".kind.home.lab")]).unwrap();
+
+        assert_eq!(rewriter.rewrite("app.kind.local"), "app.kind.home.lab");
+        assert_eq!(rewriter.rewrite("app.kind.localhost"), "app.kind.localhost");
+    }
+
+    #[test]
+    fn test_rules_apply_in_order_with_groups() {
+        let rewriter = FqdnRewriter::new(&[
+            rule(
+                r"^(?P<app>[^.]+)\.(?P<ns>[^.]+)\.svc\.cluster\.local$",
+                "${app}-${ns}.kind.local",
+            ),
+            rule(r"\.kind\.local$", ".kind.home.lab"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            rewriter.rewrite("web.shop.svc.cluster.local"),
+            "web-shop.kind.home.lab"
+        );
+    }
+
+    #[test]
+    fn test_invalid_pattern_fails() {
+        assert!(FqdnRewriter::new(&[rule("(unclosed", "")]).is_err());
+    }
+}
@@ -0,0 +1,244 @@
+// http_fetcher.rs
+use crate::auth;
+use crate::config::{ExternalSource, FieldMapping, HttpSourceConfig, SourceKind};
+use crate::dns_record::{DnsRecord, RecordType};
+use crate::dns_record_collector::DnsRecordFetcher;
+use crate::http_client;
+use crate::retry::FetchError;
+use async_trait::async_trait;
+use log::debug;
+use reqwest::Url;
+use serde_json::Value;
+use std::error::Error;
+use std::net::IpAddr;
+
+/// Fetches DNS records from any HTTP endpoint with a JSON inventory, like the Traefik API, Nomad or a CMDB.
+/// The records and their fields are found in the response with the JSON pointers of the source.
+#[derive(Default)]
+pub struct HttpDnsRecordFetcher;
+
+#[async_trait]
+impl DnsRecordFetcher for HttpDnsRecordFetcher {
+    async fn fetch_dns_records(
+        &self,
+        source: &ExternalSource,
+        ca_cert_base64: &str,
+    ) -> Result<Vec<DnsRecord>, Box<dyn Error>> {
+        let http = match &source.kind {
+            SourceKind::Http(http) => http,
+            _ => return Err("the source is not an http source".into()),
+        };
+
+        let client = http_client::build_client(source, ca_cert_base64)?;
+        let mut request = client.get(Url::parse(&source.url)?);
+        if let Some(auth) = &source.auth {
+            request = auth::apply(request, auth)?;
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(Box::new(FetchError::from_response(&response)));
+        }
+        let inventory: Value = response.json().await?;
+        map_dns_records(&inventory, http, source)
+    }
+}
+
+/// Map the entries of a JSON inventory to DNS records
+///
+/// # Arguments
+/// * `inventory` - The JSON response of the inventory
+/// * `http` - The JSON pointers to the records and their fields
+/// * `source` - The source of the inventory
+///
+/// # Returns
+/// * `Result<Vec<DnsRecord>, Box<dyn Error>>` - The A and AAAA records, entries without a fqdn or IP address are skipped, or an error when there is no records array or a pointer is invalid
+///
+pub fn map_dns_records(
+    inventory: &Value,
+    http: &HttpSourceConfig,
+    source: &ExternalSource,
+) -> Result<Vec<DnsRecord>, Box<dyn Error>> {
+    let entries = lookup(inventory, &http.records)?
+        .and_then(Value::as_array)
+        .ok_or_else(|| format!("no array of records found at {:?}", http.records))?;
+
+    let fields = &http.fields;
+    let mut dns_records = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let fqdns = match lookup(entry, &fields.fqdn)? {
+            Some(Value::Array(names)) => names.iter().filter_map(scalar).collect(),
+            Some(value) => scalar(value).into_iter().collect(),
+            None => vec![],
+        };
+        let ip = field(entry, &Some(fields.ip.clone()))?;
+        if fqdns.is_empty() || ip.is_empty() {
+            debug!(
+                "Skipping entry {} of {}, it has no fqdn at {} or no ip at {}",
+                index, source.source_name, fields.fqdn, fields.ip
+            );
+            continue;
+        }
+        let record_type = match ip.parse::<IpAddr>() {
+            Ok(IpAddr::V4(_)) => RecordType::A,
+            Ok(IpAddr::V6(_)) => RecordType::Aaaa,
+            Err(_) => {
+                debug!(
+                    "Skipping entry {} of {}, {} is not an IP address",
+                    index, source.source_name, ip
+                );
+                continue;
+            }
+        };
+
+        let template = mapped_record(entry, fields, source)?;
+        for fqdn in fqdns {
+            dns_records.push(DnsRecord {
+                fqdn: fqdn.trim_end_matches('.').to_string(),
+                ip: ip.clone(),
+                record_type,
+                ..template.clone()
+            });
+        }
+    }
+    Ok(dns_records)
+}
+
+// The record with all fields, except the fqdn and ip
+fn mapped_record(
+    entry: &Value,
+    fields: &FieldMapping,
+    source: &ExternalSource,
+) -> Result<DnsRecord, Box<dyn Error>> {
+    let cluster_name = match &fields.cluster_name {
+        Some(_) => field(entry, &fields.cluster_name)?,
+        None => source.source_name.clone(),
+    };
+    Ok(DnsRecord {
+        cluster_ip: field(entry, &fields.cluster_ip)?,
+        cluster_name,
+        controller: field(entry, &fields.controller)?,
+        kind: field(entry, &fields.kind)?,
+        namespace: field(entry, &fields.namespace)?,
+        port: field(entry, &fields.port)?,
+        ..Default::default()
+    })
+}
+
+// The string value of an optional field, empty when the field is not mapped or not found
+fn field(entry: &Value, pointer: &Option<String>) -> Result<String, Box<dyn Error>> {
+    match pointer {
+        Some(pointer) => Ok(lookup(entry, pointer)?.and_then(scalar).unwrap_or_default()),
+        None => Ok("".to_string()),
+    }
+}
+
+fn lookup<'a>(value: &'a Value, pointer: &str) -> Result<Option<&'a Value>, Box<dyn Error>> {
+    if !pointer.is_empty() && !pointer.starts_with('/') {
+        return Err(format!("{} is not a JSON pointer, it must start with a /", pointer).into());
+    }
+    Ok(value.pointer(pointer))
+}
+
+// Numbers, like ports, are used as text
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(boolean) => Some(boolean.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{record_lines, test_source, TestResponse, TestServer};
+    use serde_json::json;
+
+    fn http_source(url: &str, http: HttpSourceConfig) -> ExternalSource {
+        test_source("cmdb", "home.lab", url, SourceKind::Http(http))
+    }
+
+    #[tokio::test]
+    async fn test_fetch_records_with_field_mapping() {
+        let inventory = std::fs::read_to_string("testdata/http/inventory.json").unwrap();
+        let server = TestServer::start(move |_| TestResponse::json(&inventory)).await;
+        let source = http_source(
+            &format!("{}/api/hosts", server.url),
+            HttpSourceConfig {
+                records: "/data/hosts".to_string(),
+                fields: FieldMapping {
+                    fqdn: "/names".to_string(),
+                    ip: "/network/address".to_string(),
+                    namespace: Some("/meta/team".to_string()),
+                    port: Some("/network/port".to_string()),
+                    kind: Some("/meta/type".to_string()),
+                    ..Default::default()
+                },
+            },
+        );
+
+        let dns_records = HttpDnsRecordFetcher
+            .fetch_dns_records(&source, "")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            record_lines(&dns_records),
+            vec![
+                "nas.home.lab A 192.168.178.10 kind=server namespace=storage port=443",
+                "files.home.lab A 192.168.178.10 kind=server namespace=storage port=443",
+                "printer.home.lab A 192.168.178.30",
+            ]
+        );
+        assert!(dns_records.iter().all(|r| r.cluster_name == "cmdb"));
+    }
+
+    #[test]
+    fn test_map_records_of_top_level_array_with_default_fields() {
+        let inventory = json!([
+            { "fqdn": "a.home.lab", "ip": "192.168.178.1" },
+            { "fqdn": "b.home.lab" }
+        ]);
+        let source = http_source("", HttpSourceConfig::default());
+
+        let dns_records =
+            map_dns_records(&inventory, &HttpSourceConfig::default(), &source).unwrap();
+
+        assert_eq!(dns_records.len(), 1);
+        assert_eq!(dns_records[0].fqdn, "a.home.lab");
+    }
+
+    #[test]
+    fn test_map_records_with_ipv6_address() {
+        let inventory = json!([
+            { "fqdn": "nas.home.lab", "ip": "fd00::10" },
+            { "fqdn": "printer.home.lab", "ip": "printer" }
+        ]);
+        let source = http_source("", HttpSourceConfig::default());
+
+        let dns_records =
+            map_dns_records(&inventory, &HttpSourceConfig::default(), &source).unwrap();
+
+        assert_eq!(
+            record_lines(&dns_records),
+            vec!["nas.home.lab AAAA fd00::10"]
+        );
+    }
+
+    #[test]
+    fn test_map_records_fails_without_records_array() {
+        let http = HttpSourceConfig {
+            records: "/items".to_string(),
+            ..Default::default()
+        };
+        let source = http_source("", http.clone());
+        assert!(map_dns_records(&json!({ "data": [] }), &http, &source).is_err());
+
+        let http = HttpSourceConfig {
+            records: "items".to_string(),
+            ..Default::default()
+        };
+        assert!(map_dns_records(&json!({ "items": [] }), &http, &source).is_err());
+    }
+}
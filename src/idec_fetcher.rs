@@ -0,0 +1,223 @@
+// idec_fetcher.rs
+use crate::auth;
+use crate::config::ExternalSource;
+use crate::dns_record::DnsRecord;
+use crate::dns_record_collector::{DnsRecordFetcher, FetchOutcome};
+use crate::http_client;
+use crate::record_stream::RecordStream;
+use crate::retry::FetchError;
+use async_trait::async_trait;
+use log::info;
+use reqwest::header::{HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{StatusCode, Url};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use tokio::time::Duration;
+
+/// Fetches the DNS records from the export endpoint of an Ingress DNS Export Controller (IDEC).
+/// Remembers the ETag and Last-Modified of the last response per source, so unchanged records are not downloaded again.
+/// They are kept in memory, so the first fetch after a restart downloads the records again.
+/// Sources with a stream get their records from the stream while it is connected, and are polled otherwise.
+#[derive(Default)]
+pub struct IdecDnsRecordFetcher {
+    last_responses: Mutex<HashMap<String, LastResponse>>,
+    streams: Mutex<HashMap<String, Arc<RecordStream>>>,
+}
+
+#[derive(Clone)]
+struct LastResponse {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    dns_records: Vec<DnsRecord>,
+}
+
+impl IdecDnsRecordFetcher {
+    pub fn new() -> IdecDnsRecordFetcher {
+        IdecDnsRecordFetcher::default()
+    }
+
+    fn record_stream(&self, source: &ExternalSource) -> Arc<RecordStream> {
+        self.streams
+            .lock()
+            .unwrap()
+            .entry(source.source_name.clone())
+            .or_default()
+            .clone()
+    }
+}
+
+#[async_trait]
+impl DnsRecordFetcher for IdecDnsRecordFetcher {
+    async fn fetch_dns_records(
+        &self,
+        source: &ExternalSource,
+        ca_cert_base64: &str,
+    ) -> Result<Vec<DnsRecord>, Box<dyn Error>> {
+        self.fetch_dns_records_outcome(source, ca_cert_base64)
+            .await
+            .map(FetchOutcome::into_records)
+    }
+
+    async fn fetch_dns_records_outcome(
+        &self,
+        source: &ExternalSource,
+        ca_cert_base64: &str,
+    ) -> Result<FetchOutcome, Box<dyn Error>> {
+        if source.stream.is_some() {
+            let record_stream = self.record_stream(source);
+            if let Some(dns_records) = record_stream.records() {
+                return Ok(FetchOutcome::Fetched(dns_records));
+            }
+            // Polled until the stream is connected, the stream loads the records itself
+            record_stream.start(
+                source,
+                ca_cert_base64,
+                Box::new(IdecDnsRecordFetcher::new()),
+            );
+        }
+
+        let client = http_client::build_client(source, ca_cert_base64)?;
+        let mut request = client.get(Url::parse(&source.url)?);
+        if let Some(auth) = &source.auth {
+            request = auth::apply(request, auth)?;
+        }
+
+        let last_response = self
+            .last_responses
+            .lock()
+            .unwrap()
+            .get(&source.source_name)
+            .cloned();
+        if let Some(last_response) = &last_response {
+            if let Some(etag) = &last_response.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &last_response.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        let response = request.send().await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            if let Some(last_response) = last_response {
+                info!(
+                    "DNS records of {} are not modified since the last fetch",
+                    source.source_name
+                );
+                return Ok(FetchOutcome::NotModified(last_response.dns_records));
+            }
+        }
+        if !response.status().is_success() {
+            return Err(Box::new(FetchError::from_response(&response)));
+        }
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(String::from)
+        };
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+        let dns_records: Vec<DnsRecord> = response.json().await?;
+
+        if etag.is_some() || last_modified.is_some() {
+            self.last_responses.lock().unwrap().insert(
+                source.source_name.clone(),
+                LastResponse {
+                    etag,
+                    last_modified,
+                    dns_records: dns_records.clone(),
+                },
+            );
+        }
+        Ok(FetchOutcome::Fetched(dns_records))
+    }
+
+    async fn wait_for_change(&self, source: &ExternalSource) {
+        match &source.stream {
+            Some(stream) => {
+                self.record_stream(source)
+                    .wait_for_change(Duration::from_secs(stream.debounce_in_seconds))
+                    .await
+            }
+            None => std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retry;
+    use crate::test_support::{TestResponse, TestServer};
+
+    const IDEC_EXPORT: &str = r#"[{
+        "clusterIP": "10.96.0.10",
+        "clusterName": "kind",
+        "controller": "istio",
+        "fqdn": "app.example.com",
+        "ip": "172.19.255.200",
+        "kind": "VirtualService",
+        "namespace": "default",
+        "port": "80"
+    }]"#;
+
+    #[tokio::test]
+    async fn test_idec_fetcher_reuses_records_when_not_modified() {
+        let server = TestServer::start(|request| {
+            if request.headers.get("if-none-match") == Some(&"\"v1\"".to_string()) {
+                return TestResponse::status(304);
+            }
+            TestResponse::json(IDEC_EXPORT)
+                .with_header("ETag", "\"v1\"")
+                .with_header("Last-Modified", "Wed, 21 Oct 2015 07:28:00 GMT")
+        })
+        .await;
+        let source = ExternalSource {
+            url: format!("{}/export", server.url),
+            source_name: "kind".to_string(),
+            ..Default::default()
+        };
+        let fetcher = IdecDnsRecordFetcher::new();
+
+        let first = fetcher.fetch_dns_records(&source, "").await.unwrap();
+        let second = fetcher.fetch_dns_records(&source, "").await.unwrap();
+
+        assert_eq!(first[0].fqdn, "app.example.com");
+        assert_eq!(first, second);
+        let third = fetcher
+            .fetch_dns_records_outcome(&source, "")
+            .await
+            .unwrap();
+        assert_eq!(third, FetchOutcome::NotModified(first));
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(requests[0].path, "/export");
+        assert_eq!(requests[0].headers.get("if-none-match"), None);
+        assert_eq!(requests[1].headers["if-none-match"], "\"v1\"");
+        assert_eq!(
+            requests[1].headers["if-modified-since"],
+            "Wed, 21 Oct 2015 07:28:00 GMT"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_idec_fetcher_fails_on_error_status() {
+        let server = TestServer::start(|_| TestResponse::status(503)).await;
+        let source = ExternalSource {
+            url: format!("{}/export", server.url),
+            source_name: "kind".to_string(),
+            ..Default::default()
+        };
+
+        let error = IdecDnsRecordFetcher::new()
+            .fetch_dns_records(&source, "")
+            .await
+            .unwrap_err();
+        assert!(retry::is_transient(error.as_ref()));
+    }
+}
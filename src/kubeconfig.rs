@@ -0,0 +1,220 @@
+// kubeconfig.rs
+use crate::config::TlsConfig;
+use serde::Deserialize;
+use std::error::Error;
+use std::path::Path;
+
+// Only the parts of a kubeconfig file needed to connect to the API server
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, rename_all = "kebab-case")]
+struct Kubeconfig {
+    current_context: String,
+    contexts: Vec<NamedContext>,
+    clusters: Vec<NamedCluster>,
+    users: Vec<NamedUser>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct NamedContext {
+    name: String,
+    context: Context,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct Context {
+    cluster: String,
+    user: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct NamedCluster {
+    name: String,
+    cluster: Cluster,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, rename_all = "kebab-case")]
+struct Cluster {
+    server: String,
+    certificate_authority: Option<String>,
+    certificate_authority_data: Option<String>,
+    insecure_skip_tls_verify: bool,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct NamedUser {
+    name: String,
+    user: User,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, rename_all = "kebab-case")]
+struct User {
+    token: Option<String>,
+    #[serde(rename = "tokenFile")]
+    token_file: Option<String>,
+    client_certificate: Option<String>,
+    client_certificate_data: Option<String>,
+    client_key: Option<String>,
+    client_key_data: Option<String>,
+    exec: Option<serde_yaml::Value>,
+}
+
+/// How to reach the API server of a cluster, as described by a kubeconfig context
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClusterConnection {
+    pub server: String,
+    pub token: Option<String>,
+    pub tls: TlsConfig,
+}
+
+/// Read the connection details of a context from a kubeconfig file
+///
+/// # Arguments
+/// * `kubeconfig_path` - A string containing the path to the kubeconfig file
+/// * `context_name` - The context to use, the current context of the kubeconfig when not set
+///
+/// # Returns
+/// * `Result<ClusterConnection, Box<dyn Error>>` - The connection details, or an error when the context can't be used
+///
+pub fn load_cluster_connection(
+    kubeconfig_path: &str,
+    context_name: Option<&str>,
+) -> Result<ClusterConnection, Box<dyn Error>> {
+    let content = std::fs::read_to_string(kubeconfig_path)
+        .map_err(|e| format!("failed to read kubeconfig {} - {}", kubeconfig_path, e))?;
+    let kubeconfig: Kubeconfig = serde_yaml::from_str(&content)
+        .map_err(|e| format!("failed to parse kubeconfig {} - {}", kubeconfig_path, e))?;
+    // Relative paths in a kubeconfig are relative to the kubeconfig file itself
+    let base_directory = Path::new(kubeconfig_path)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    let resolve = |path: &String| base_directory.join(path).display().to_string();
+
+    let context_name = context_name.unwrap_or(&kubeconfig.current_context);
+    let context = kubeconfig
+        .contexts
+        .iter()
+        .find(|context| context.name == context_name)
+        .ok_or_else(|| format!("context {} not found in kubeconfig", context_name))?;
+    let cluster = kubeconfig
+        .clusters
+        .iter()
+        .find(|cluster| cluster.name == context.context.cluster)
+        .ok_or_else(|| {
+            format!(
+                "cluster {} not found in kubeconfig",
+                context.context.cluster
+            )
+        })?;
+    let user = kubeconfig
+        .users
+        .iter()
+        .find(|user| user.name == context.context.user)
+        .map(|user| &user.user);
+
+    if user.is_some_and(|user| user.exec.is_some()) {
+        return Err(format!(
+            "user {} authenticates with an exec plugin, which is not supported, use a token instead",
+            context.context.user
+        )
+        .into());
+    }
+
+    let token = match user {
+        Some(User {
+            token: Some(token), ..
+        }) => Some(token.clone()),
+        Some(User {
+            token_file: Some(token_file),
+            ..
+        }) => {
+            let token = std::fs::read_to_string(resolve(token_file))
+                .map_err(|e| format!("failed to read tokenFile {} - {}", token_file, e))?;
+            Some(token.trim().to_string())
+        }
+        _ => None,
+    };
+
+    let tls = TlsConfig {
+        ca_cert_base64: cluster.cluster.certificate_authority_data.clone(),
+        ca_bundle_path: cluster.cluster.certificate_authority.as_ref().map(resolve),
+        insecure_skip_verify: cluster.cluster.insecure_skip_tls_verify,
+        client_cert_base64: user.and_then(|user| user.client_certificate_data.clone()),
+        client_cert_path: user.and_then(|user| user.client_certificate.as_ref().map(resolve)),
+        client_key_base64: user.and_then(|user| user.client_key_data.clone()),
+        client_key_path: user.and_then(|user| user.client_key.as_ref().map(resolve)),
+        ..Default::default()
+    };
+
+    Ok(ClusterConnection {
+        server: cluster.cluster.server.clone(),
+        token,
+        tls,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ExternalSource;
+    use crate::http_client;
+
+    const KUBECONFIG_PATH: &str = "testdata/kubernetes/kubeconfig.yaml";
+
+    #[test]
+    fn test_load_cluster_connection_of_current_context() {
+        let connection = load_cluster_connection(KUBECONFIG_PATH, None).unwrap();
+        assert_eq!(connection.server, "https://127.0.0.1:6443");
+        assert_eq!(connection.token, Some("kind-token".to_string()));
+        assert_eq!(
+            connection.tls.ca_cert_base64,
+            Some("Y2EtZGF0YQ==".to_string())
+        );
+        assert!(!connection.tls.insecure_skip_verify);
+    }
+
+    #[test]
+    fn test_load_cluster_connection_of_other_context() {
+        let connection = load_cluster_connection(KUBECONFIG_PATH, Some("lab")).unwrap();
+        assert_eq!(connection.server, "https://lab.home.lab:6443");
+        assert_eq!(connection.token, None);
+        assert_eq!(
+            connection.tls.ca_bundle_path,
+            Some("testdata/kubernetes/lab-ca.crt".to_string())
+        );
+        assert_eq!(
+            connection.tls.client_cert_path,
+            Some("testdata/kubernetes/lab.crt".to_string())
+        );
+        assert!(connection.tls.insecure_skip_verify);
+    }
+
+    #[test]
+    fn test_load_cluster_connection_with_kind_client_certificate() {
+        // kind writes the client key as a PKCS#1 RSA key
+        let connection = load_cluster_connection(KUBECONFIG_PATH, Some("kind-admin")).unwrap();
+        let source = ExternalSource {
+            source_name: "kind".to_string(),
+            tls: Some(connection.tls),
+            ..Default::default()
+        };
+
+        let result = http_client::build_client(&source, "");
+        assert!(result.is_ok(), "Failed to build client: {:?}", result.err());
+    }
+
+    #[test]
+    fn test_load_cluster_connection_of_unknown_context_fails() {
+        assert!(load_cluster_connection(KUBECONFIG_PATH, Some("unknown")).is_err());
+    }
+
+    #[test]
+    fn test_load_cluster_connection_with_exec_plugin_fails() {
+        assert!(load_cluster_connection(KUBECONFIG_PATH, Some("cloud")).is_err());
+    }
+}
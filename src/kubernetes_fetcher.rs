@@ -0,0 +1,864 @@
+// kubernetes_fetcher.rs
+use crate::auth;
+use crate::config::{ExternalSource, KubernetesConfig, KubernetesResource, SourceKind};
+use crate::dns_record::DnsRecord;
+use crate::dns_record_collector::DnsRecordFetcher;
+use crate::http_client;
+use crate::kubeconfig;
+use crate::retry::FetchError;
+use async_trait::async_trait;
+use log::{debug, warn};
+use reqwest::{Client, Url};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Fetches DNS records from the Kubernetes API directly, without IDEC.
+/// Depending on the configured resources, a DNS record is created per hostname of:
+/// * a `LoadBalancer` Service with a hostname annotation, pointing to the address of its load balancer
+/// * an Ingress rule, pointing to the load balancer address in the status of the Ingress
+/// * an HTTPRoute, pointing to the address of its parent Gateway
+/// * an Istio VirtualService, pointing to the load balancer address of the ingress gateway Service selected by its Gateway
+#[derive(Default)]
+pub struct KubernetesDnsRecordFetcher;
+
+#[async_trait]
+impl DnsRecordFetcher for KubernetesDnsRecordFetcher {
+    async fn fetch_dns_records(
+        &self,
+        source: &ExternalSource,
+        ca_cert_base64: &str,
+    ) -> Result<Vec<DnsRecord>, Box<dyn Error>> {
+        let kubernetes = match &source.kind {
+            SourceKind::Kubernetes(kubernetes) => kubernetes,
+            _ => return Err("the source is not a kubernetes source".into()),
+        };
+        let api = KubernetesApi::connect(source, kubernetes, ca_cert_base64)?;
+        let namespace = &kubernetes.namespace;
+
+        let mut dns_records = Vec::new();
+        for resource in &kubernetes.resources {
+            match resource {
+                KubernetesResource::Services => {
+                    let services: Vec<Service> = api
+                        .list(&namespaced_path("/api/v1", namespace, "services"))
+                        .await?;
+                    dns_records.extend(services_to_dns_records(&services, source, kubernetes));
+                }
+                KubernetesResource::Ingresses => {
+                    let ingresses: Vec<Ingress> = api
+                        .list(&namespaced_path(NETWORKING_API, namespace, "ingresses"))
+                        .await?;
+                    dns_records.extend(ingresses_to_dns_records(&ingresses, source));
+                }
+                KubernetesResource::HttpRoutes => {
+                    let routes: Vec<HttpRoute> = api
+                        .list(&namespaced_path(GATEWAY_API, namespace, "httproutes"))
+                        .await?;
+                    // Routes may attach to Gateways in other namespaces
+                    let gateways: Vec<Gateway> = api
+                        .list(&namespaced_path(GATEWAY_API, &None, "gateways"))
+                        .await?;
+                    dns_records.extend(http_routes_to_dns_records(&routes, &gateways, source));
+                }
+                KubernetesResource::VirtualServices => {
+                    let virtual_services: Vec<VirtualService> = api
+                        .list(&namespaced_path(ISTIO_API, namespace, "virtualservices"))
+                        .await?;
+                    // Gateways and their ingress gateway Services usually live in another namespace
+                    let gateways: Vec<IstioGateway> = api
+                        .list(&namespaced_path(ISTIO_API, &None, "gateways"))
+                        .await?;
+                    let services: Vec<Service> = api
+                        .list(&namespaced_path("/api/v1", &None, "services"))
+                        .await?;
+                    dns_records.extend(virtual_services_to_dns_records(
+                        &virtual_services,
+                        &gateways,
+                        &services,
+                        source,
+                    ));
+                }
+            }
+        }
+        Ok(dns_records)
+    }
+}
+
+const NETWORKING_API: &str = "/apis/networking.k8s.io/v1";
+const GATEWAY_API: &str = "/apis/gateway.networking.k8s.io/v1";
+const ISTIO_API: &str = "/apis/networking.istio.io/v1beta1";
+
+// A client for the API server of a single cluster
+pub struct KubernetesApi {
+    client: Client,
+    server: Url,
+    token: Option<String>,
+    source: ExternalSource,
+}
+
+impl KubernetesApi {
+    pub fn connect(
+        source: &ExternalSource,
+        kubernetes: &KubernetesConfig,
+        ca_cert_base64: &str,
+    ) -> Result<KubernetesApi, Box<dyn Error>> {
+        match &kubernetes.kubeconfig_path {
+            Some(kubeconfig_path) => {
+                let connection = kubeconfig::load_cluster_connection(
+                    kubeconfig_path,
+                    kubernetes.context.as_deref(),
+                )?;
+                // The cluster of the kubeconfig brings its own CA certificate
+                let cluster_source = ExternalSource {
+                    tls: Some(connection.tls),
+                    ..source.clone()
+                };
+                Ok(KubernetesApi {
+                    client: http_client::build_client(&cluster_source, "")?,
+                    server: Url::parse(&connection.server)?,
+                    token: connection.token,
+                    source: source.clone(),
+                })
+            }
+            None => Ok(KubernetesApi {
+                client: http_client::build_client(source, ca_cert_base64)?,
+                server: Url::parse(&source.url)?,
+                token: None,
+                source: source.clone(),
+            }),
+        }
+    }
+
+    /// List all resources at the path, following the continue tokens of paginated responses
+    pub async fn list<T: DeserializeOwned>(&self, path: &str) -> Result<Vec<T>, Box<dyn Error>> {
+        let mut items = Vec::new();
+        let mut continue_token: Option<String> = None;
+        loop {
+            // Keep any path of the server, as used by proxies like Rancher
+            let mut url = Url::parse(&format!(
+                "{}{}",
+                self.server.as_str().trim_end_matches('/'),
+                path
+            ))?;
+            url.query_pairs_mut().append_pair("limit", "500");
+            if let Some(continue_token) = &continue_token {
+                url.query_pairs_mut()
+                    .append_pair("continue", continue_token);
+            }
+
+            let mut request = self.client.get(url);
+            if let Some(token) = &self.token {
+                request = request.bearer_auth(token);
+            } else if let Some(auth) = &self.source.auth {
+                request = auth::apply(request, auth)?;
+            }
+            let response = request.send().await?;
+            if !response.status().is_success() {
+                return Err(Box::new(FetchError::from_response(&response)));
+            }
+
+            let list: List<T> = response.json().await?;
+            items.extend(list.items);
+            continue_token = list
+                .metadata
+                .continue_token
+                .filter(|token| !token.is_empty());
+            if continue_token.is_none() {
+                return Ok(items);
+            }
+        }
+    }
+}
+
+// The path of a resource collection, limited to a namespace when one is configured
+pub fn namespaced_path(api_prefix: &str, namespace: &Option<String>, resource: &str) -> String {
+    match namespace {
+        Some(namespace) => format!("{}/namespaces/{}/{}", api_prefix, namespace, resource),
+        None => format!("{}/{}", api_prefix, resource),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct List<T> {
+    #[serde(default = "Vec::new")]
+    pub items: Vec<T>,
+    #[serde(default)]
+    pub metadata: ListMeta,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ListMeta {
+    #[serde(rename = "continue")]
+    pub continue_token: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct ObjectMeta {
+    pub name: String,
+    pub namespace: String,
+    pub annotations: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Service {
+    pub metadata: ObjectMeta,
+    pub spec: ServiceSpec,
+    pub status: ServiceStatus,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ServiceSpec {
+    #[serde(rename = "type")]
+    pub service_type: String,
+    #[serde(rename = "clusterIP")]
+    pub cluster_ip: String,
+    pub load_balancer_class: Option<String>,
+    pub ports: Vec<ServicePort>,
+    pub selector: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct ServicePort {
+    pub port: u16,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ServiceStatus {
+    pub load_balancer: LoadBalancerStatus,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct LoadBalancerStatus {
+    pub ingress: Vec<LoadBalancerIngress>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct LoadBalancerIngress {
+    pub ip: Option<String>,
+    pub hostname: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Ingress {
+    pub metadata: ObjectMeta,
+    pub spec: IngressSpec,
+    pub status: ServiceStatus,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, rename_all = "camelCase")]
+pub struct IngressSpec {
+    pub ingress_class_name: Option<String>,
+    pub rules: Vec<IngressRule>,
+    pub tls: Vec<IngressTls>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct IngressRule {
+    pub host: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct IngressTls {
+    pub hosts: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct HttpRoute {
+    pub metadata: ObjectMeta,
+    pub spec: HttpRouteSpec,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, rename_all = "camelCase")]
+pub struct HttpRouteSpec {
+    pub parent_refs: Vec<ParentReference>,
+    pub hostnames: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct ParentReference {
+    pub kind: Option<String>,
+    pub namespace: Option<String>,
+    pub name: String,
+    pub port: Option<u16>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Gateway {
+    pub metadata: ObjectMeta,
+    pub status: GatewayStatus,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct GatewayStatus {
+    pub addresses: Vec<GatewayAddress>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct GatewayAddress {
+    #[serde(rename = "type")]
+    pub address_type: Option<String>,
+    pub value: String,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct VirtualService {
+    pub metadata: ObjectMeta,
+    pub spec: VirtualServiceSpec,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct VirtualServiceSpec {
+    pub hosts: Vec<String>,
+    // The Gateways as `<namespace>/<name>` or `<name>`, `mesh` for the sidecars
+    pub gateways: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct IstioGateway {
+    pub metadata: ObjectMeta,
+    pub spec: IstioGatewaySpec,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct IstioGatewaySpec {
+    // The labels of the ingress gateway pods this Gateway configures
+    pub selector: BTreeMap<String, String>,
+    pub servers: Vec<IstioServer>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct IstioServer {
+    pub port: IstioPort,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct IstioPort {
+    pub number: u16,
+}
+
+impl GatewayStatus {
+    // The first IP address assigned to the Gateway, the address type defaults to IPAddress
+    pub fn ip(&self) -> Option<String> {
+        self.addresses
+            .iter()
+            .find(|address| address.address_type.as_deref().unwrap_or("IPAddress") == "IPAddress")
+            .map(|address| address.value.clone())
+    }
+}
+
+impl LoadBalancerStatus {
+    // The first IP address assigned to the load balancer
+    pub fn ip(&self) -> Option<String> {
+        self.ingress.iter().find_map(|ingress| ingress.ip.clone())
+    }
+}
+
+// The comma separated hostnames in the hostname annotation of a resource
+fn annotated_hostnames(metadata: &ObjectMeta, hostname_annotation: &str) -> Vec<String> {
+    metadata
+        .annotations
+        .get(hostname_annotation)
+        .map(|hostnames| {
+            hostnames
+                .split(',')
+                .map(|hostname| hostname.trim().trim_end_matches('.').to_string())
+                .filter(|hostname| !hostname.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn services_to_dns_records(
+    services: &[Service],
+    source: &ExternalSource,
+    kubernetes: &KubernetesConfig,
+) -> Vec<DnsRecord> {
+    let mut dns_records = Vec::new();
+    for service in services {
+        if service.spec.service_type != "LoadBalancer" {
+            continue;
+        }
+        let hostnames = annotated_hostnames(&service.metadata, &kubernetes.hostname_annotation);
+        if hostnames.is_empty() {
+            debug!(
+                "Skipping Service {}/{}, it has no {} annotation",
+                service.metadata.namespace, service.metadata.name, kubernetes.hostname_annotation
+            );
+            continue;
+        }
+        let ip = match service.status.load_balancer.ip() {
+            Some(ip) => ip,
+            None => {
+                warn!(
+                    "Skipping Service {}/{}, its load balancer has no IP address (yet)",
+                    service.metadata.namespace, service.metadata.name
+                );
+                continue;
+            }
+        };
+
+        for hostname in hostnames {
+            dns_records.push(DnsRecord {
+                cluster_ip: service.spec.cluster_ip.clone(),
+                cluster_name: source.source_name.clone(),
+                controller: service.spec.load_balancer_class.clone().unwrap_or_default(),
+                fqdn: hostname,
+                ip: ip.clone(),
+                kind: "Service".to_string(),
+                namespace: service.metadata.namespace.clone(),
+                port: service
+                    .spec
+                    .ports
+                    .first()
+                    .map(|port| port.port.to_string())
+                    .unwrap_or_default(),
+                ..Default::default()
+            });
+        }
+    }
+    dns_records
+}
+
+fn ingresses_to_dns_records(ingresses: &[Ingress], source: &ExternalSource) -> Vec<DnsRecord> {
+    let mut dns_records = Vec::new();
+    for ingress in ingresses {
+        let ip = match ingress.status.load_balancer.ip() {
+            Some(ip) => ip,
+            None => {
+                warn!(
+                    "Skipping Ingress {}/{}, it has no load balancer IP address (yet)",
+                    ingress.metadata.namespace, ingress.metadata.name
+                );
+                continue;
+            }
+        };
+        let controller = ingress
+            .spec
+            .ingress_class_name
+            .clone()
+            .or_else(|| {
+                ingress
+                    .metadata
+                    .annotations
+                    .get("kubernetes.io/ingress.class")
+                    .cloned()
+            })
+            .unwrap_or_default();
+
+        let mut hosts: Vec<String> = ingress
+            .spec
+            .rules
+            .iter()
+            .filter_map(|rule| rule.host.clone())
+            .collect();
+        hosts.sort();
+        hosts.dedup();
+        for host in hosts {
+            let is_tls = ingress.spec.tls.iter().any(|tls| tls.hosts.contains(&host));
+            dns_records.push(DnsRecord {
+                cluster_name: source.source_name.clone(),
+                controller: controller.clone(),
+                fqdn: host,
+                ip: ip.clone(),
+                kind: "Ingress".to_string(),
+                namespace: ingress.metadata.namespace.clone(),
+                port: if is_tls { "443" } else { "80" }.to_string(),
+                ..Default::default()
+            });
+        }
+    }
+    dns_records
+}
+
+fn http_routes_to_dns_records(
+    routes: &[HttpRoute],
+    gateways: &[Gateway],
+    source: &ExternalSource,
+) -> Vec<DnsRecord> {
+    let mut dns_records = Vec::new();
+    for route in routes {
+        // A route attached to multiple Gateways resolves to the first one with an address
+        let parent = route
+            .spec
+            .parent_refs
+            .iter()
+            .filter(|parent| parent.kind.as_deref().unwrap_or("Gateway") == "Gateway")
+            .find_map(|parent| {
+                let namespace = parent
+                    .namespace
+                    .as_ref()
+                    .unwrap_or(&route.metadata.namespace);
+                gateways
+                    .iter()
+                    .find(|gateway| {
+                        gateway.metadata.name == parent.name
+                            && &gateway.metadata.namespace == namespace
+                    })
+                    .and_then(|gateway| gateway.status.ip())
+                    .map(|ip| (parent, ip))
+            });
+        let (parent, ip) = match parent {
+            Some(parent) => parent,
+            None => {
+                warn!(
+                    "Skipping HTTPRoute {}/{}, none of its parent Gateways has an IP address (yet)",
+                    route.metadata.namespace, route.metadata.name
+                );
+                continue;
+            }
+        };
+
+        for hostname in &route.spec.hostnames {
+            dns_records.push(DnsRecord {
+                cluster_name: source.source_name.clone(),
+                controller: parent.name.clone(),
+                fqdn: hostname.clone(),
+                ip: ip.clone(),
+                kind: "HTTPRoute".to_string(),
+                namespace: route.metadata.namespace.clone(),
+                port: parent.port.map(|port| port.to_string()).unwrap_or_default(),
+                ..Default::default()
+            });
+        }
+    }
+    dns_records
+}
+
+fn virtual_services_to_dns_records(
+    virtual_services: &[VirtualService],
+    gateways: &[IstioGateway],
+    services: &[Service],
+    source: &ExternalSource,
+) -> Vec<DnsRecord> {
+    let mut dns_records = Vec::new();
+    for virtual_service in virtual_services {
+        // A VirtualService bound to multiple Gateways resolves to the first one with an ingress gateway address
+        let resolved = virtual_service
+            .spec
+            .gateways
+            .iter()
+            .filter(|gateway| gateway.as_str() != "mesh")
+            .find_map(|gateway| {
+                let (namespace, name) = gateway
+                    .split_once('/')
+                    .unwrap_or((&virtual_service.metadata.namespace, gateway));
+                let gateway = gateways.iter().find(|candidate| {
+                    candidate.metadata.name == name && candidate.metadata.namespace == namespace
+                })?;
+                let ip = ingress_gateway_ip(gateway, services)?;
+                Some((gateway, ip))
+            });
+        let (gateway, ip) = match resolved {
+            Some(resolved) => resolved,
+            None => {
+                warn!(
+                    "Skipping VirtualService {}/{}, none of its Gateways selects an ingress gateway Service with an IP address (yet)",
+                    virtual_service.metadata.namespace, virtual_service.metadata.name
+                );
+                continue;
+            }
+        };
+
+        for host in &virtual_service.spec.hosts {
+            if host.contains('*') {
+                debug!(
+                    "Skipping wildcard host {} of VirtualService {}/{}",
+                    host, virtual_service.metadata.namespace, virtual_service.metadata.name
+                );
+                continue;
+            }
+            dns_records.push(DnsRecord {
+                cluster_name: source.source_name.clone(),
+                controller: gateway.metadata.name.clone(),
+                fqdn: host.clone(),
+                ip: ip.clone(),
+                kind: "VirtualService".to_string(),
+                namespace: virtual_service.metadata.namespace.clone(),
+                port: gateway
+                    .spec
+                    .servers
+                    .first()
+                    .map(|server| server.port.number.to_string())
+                    .unwrap_or_default(),
+                ..Default::default()
+            });
+        }
+    }
+    dns_records
+}
+
+// The load balancer IP of the ingress gateway Service, the LoadBalancer Service selecting the pods the Gateway selects.
+// A Service in the namespace of the Gateway is preferred.
+fn ingress_gateway_ip(gateway: &IstioGateway, services: &[Service]) -> Option<String> {
+    if gateway.spec.selector.is_empty() {
+        return None;
+    }
+    let mut candidates: Vec<&Service> = services
+        .iter()
+        .filter(|service| service.spec.service_type == "LoadBalancer")
+        .filter(|service| {
+            gateway
+                .spec
+                .selector
+                .iter()
+                .all(|(key, value)| service.spec.selector.get(key) == Some(value))
+        })
+        .collect();
+    candidates.sort_by_key(|service| service.metadata.namespace != gateway.metadata.namespace);
+    candidates
+        .iter()
+        .find_map(|service| service.status.load_balancer.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AuthConfig, Secret};
+    use crate::test_support::{record_lines, test_source, TestResponse, TestServer};
+
+    fn kubernetes_source(url: &str) -> ExternalSource {
+        let kubernetes = KubernetesConfig::default();
+        test_source(
+            "kind",
+            "example.com",
+            url,
+            SourceKind::Kubernetes(kubernetes),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_fetch_load_balancer_services() {
+        let services = std::fs::read_to_string("testdata/kubernetes/services.json").unwrap();
+        let server = TestServer::start(move |request| {
+            if request.path.starts_with("/api/v1/services") {
+                TestResponse::json(&services)
+            } else {
+                TestResponse::status(404)
+            }
+        })
+        .await;
+
+        let mut source = kubernetes_source(&server.url);
+        source.auth = Some(AuthConfig {
+            bearer_token: Some(Secret::File("testdata/kubernetes/token".to_string())),
+            ..Default::default()
+        });
+
+        let mut dns_records = KubernetesDnsRecordFetcher
+            .fetch_dns_records(&source, "")
+            .await
+            .unwrap();
+        dns_records.sort_by_key(|record| record.fqdn.clone());
+
+        let fqdns: Vec<&str> = dns_records.iter().map(|r| r.fqdn.as_str()).collect();
+        assert_eq!(
+            fqdns,
+            vec![
+                "grafana.example.com",
+                "prometheus.example.com",
+                "shop.example.com"
+            ]
+        );
+        assert_eq!(dns_records[2].ip, "172.19.255.201");
+        assert_eq!(dns_records[2].cluster_ip, "10.96.12.34");
+        assert_eq!(dns_records[2].cluster_name, "kind");
+        assert_eq!(dns_records[2].kind, "Service");
+        assert_eq!(dns_records[2].namespace, "shop");
+        assert_eq!(dns_records[2].port, "443");
+        assert_eq!(
+            server.requests()[0].headers["authorization"],
+            "Bearer service-account-token"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_follows_continue_tokens() {
+        let server = TestServer::start(|request| {
+            let page = if request.path.contains("continue=page-2") {
+                r#"{"items": [{"metadata": {"name": "b", "namespace": "default", "annotations": {"external-dns.alpha.kubernetes.io/hostname": "b.example.com"}}, "spec": {"type": "LoadBalancer"}, "status": {"loadBalancer": {"ingress": [{"ip": "172.19.255.202"}]}}}], "metadata": {"continue": ""}}"#
+            } else {
+                r#"{"items": [{"metadata": {"name": "a", "namespace": "default", "annotations": {"external-dns.alpha.kubernetes.io/hostname": "a.example.com"}}, "spec": {"type": "LoadBalancer"}, "status": {"loadBalancer": {"ingress": [{"ip": "172.19.255.201"}]}}}], "metadata": {"continue": "page-2"}}"#
+            };
+            TestResponse::json(page)
+        })
+        .await;
+
+        let mut source = kubernetes_source(&server.url);
+        source.kind = SourceKind::Kubernetes(KubernetesConfig {
+            namespace: Some("default".to_string()),
+            ..Default::default()
+        });
+
+        let dns_records = KubernetesDnsRecordFetcher
+            .fetch_dns_records(&source, "")
+            .await
+            .unwrap();
+        assert_eq!(dns_records.len(), 2);
+        let requests = server.requests();
+        assert!(requests[0]
+            .path
+            .starts_with("/api/v1/namespaces/default/services?limit=500"));
+        assert!(requests[1].path.contains("continue=page-2"));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_with_kubeconfig() {
+        let services = std::fs::read_to_string("testdata/kubernetes/services.json").unwrap();
+        let server = TestServer::start(move |_| TestResponse::json(&services)).await;
+        let kubeconfig_path = "testdata/kubernetes/test_fetch_with_kubeconfig.yaml";
+        let kubeconfig = std::fs::read_to_string("testdata/kubernetes/kubeconfig.yaml")
+            .unwrap()
+            .replace("https://127.0.0.1:6443", &server.url)
+            .replace("certificate-authority-data: Y2EtZGF0YQ==", "");
+        std::fs::write(kubeconfig_path, kubeconfig).unwrap();
+
+        let mut source = kubernetes_source("");
+        source.kind = SourceKind::Kubernetes(KubernetesConfig {
+            kubeconfig_path: Some(kubeconfig_path.to_string()),
+            ..Default::default()
+        });
+
+        let result = KubernetesDnsRecordFetcher
+            .fetch_dns_records(&source, "")
+            .await;
+
+        // Clean up
+        std::fs::remove_file(kubeconfig_path).unwrap();
+
+        assert_eq!(result.unwrap().len(), 3);
+        assert_eq!(
+            server.requests()[0].headers["authorization"],
+            "Bearer kind-token"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_ingresses_and_http_routes() {
+        let server = TestServer::start(|request| {
+            let fixture = if request
+                .path
+                .starts_with("/apis/networking.k8s.io/v1/ingresses")
+            {
+                "testdata/kubernetes/ingresses.json"
+            } else if request
+                .path
+                .starts_with("/apis/gateway.networking.k8s.io/v1/httproutes")
+            {
+                "testdata/kubernetes/httproutes.json"
+            } else if request
+                .path
+                .starts_with("/apis/gateway.networking.k8s.io/v1/gateways")
+            {
+                "testdata/kubernetes/gateways.json"
+            } else {
+                return TestResponse::status(404);
+            };
+            TestResponse::json(&std::fs::read_to_string(fixture).unwrap())
+        })
+        .await;
+
+        let mut source = kubernetes_source(&server.url);
+        source.kind = SourceKind::Kubernetes(KubernetesConfig {
+            resources: vec![
+                KubernetesResource::Ingresses,
+                KubernetesResource::HttpRoutes,
+            ],
+            ..Default::default()
+        });
+
+        let dns_records = KubernetesDnsRecordFetcher
+            .fetch_dns_records(&source, "")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            record_lines(&dns_records),
+            vec![
+                "argo.example.com A 172.19.255.210 kind=Ingress namespace=argocd controller=nginx port=443",
+                "legacy.example.com A 172.19.255.211 kind=Ingress namespace=legacy controller=traefik port=80",
+                "store.example.com A 172.19.255.220 kind=HTTPRoute namespace=store controller=public port=443",
+                "api.store.example.com A 172.19.255.220 kind=HTTPRoute namespace=store controller=public port=443",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ingress_with_repeated_host_has_one_record_per_host() {
+        let ingress: Ingress = serde_json::from_str(
+            r#"{"metadata": {"name": "shop", "namespace": "shop"}, "spec": {"rules": [{"host": "shop.example.com"}, {"host": "api.example.com"}, {"host": "shop.example.com"}]}, "status": {"loadBalancer": {"ingress": [{"ip": "172.19.255.212"}]}}}"#,
+        )
+        .unwrap();
+
+        let dns_records = ingresses_to_dns_records(&[ingress], &kubernetes_source(""));
+
+        let fqdns: Vec<&str> = dns_records.iter().map(|r| r.fqdn.as_str()).collect();
+        assert_eq!(fqdns, vec!["api.example.com", "shop.example.com"]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_virtual_services() {
+        let server = TestServer::start(|request| {
+            let fixture = if request
+                .path
+                .starts_with("/apis/networking.istio.io/v1beta1/virtualservices")
+            {
+                "testdata/kubernetes/virtualservices.json"
+            } else if request
+                .path
+                .starts_with("/apis/networking.istio.io/v1beta1/gateways")
+            {
+                "testdata/kubernetes/istio-gateways.json"
+            } else if request.path.starts_with("/api/v1/services") {
+                "testdata/kubernetes/istio-services.json"
+            } else {
+                return TestResponse::status(404);
+            };
+            TestResponse::json(&std::fs::read_to_string(fixture).unwrap())
+        })
+        .await;
+
+        let mut source = kubernetes_source(&server.url);
+        source.kind = SourceKind::Kubernetes(KubernetesConfig {
+            resources: vec![KubernetesResource::VirtualServices],
+            ..Default::default()
+        });
+
+        let dns_records = KubernetesDnsRecordFetcher
+            .fetch_dns_records(&source, "")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            record_lines(&dns_records),
+            vec![
+                "idec.example.com A 172.19.255.230 kind=VirtualService namespace=idec controller=idec port=80",
+                "shop.example.com A 172.19.255.231 kind=VirtualService namespace=shop controller=internal port=443",
+            ]
+        );
+    }
+}
@@ -1,18 +1,39 @@
 // main.rs
+mod auth;
+mod axfr_fetcher;
+mod command_fetcher;
 mod config;
+mod consul_fetcher;
+mod dhcp_fetcher;
 mod dns_record;
 mod dns_record_collector;
+mod docker_fetcher;
+mod duplicate_policy;
+mod fetcher_registry;
+mod file_fetcher;
 mod file_writer;
 mod file_watcher;
+mod fqdn_rewriter;
+mod http_client;
+mod http_fetcher;
+mod idec_fetcher;
+mod kubeconfig;
+mod kubernetes_fetcher;
+mod record_cache;
+mod record_filter;
+mod record_stream;
+mod retry;
+#[cfg(test)]
+mod test_support;
 
 use std::collections::HashMap;
 
 use dns_record::DnsRecord;
 use notify::{RecommendedWatcher, RecursiveMode, Watcher};
-use tokio::{sync::mpsc, time::{sleep, Duration}};
+use tokio::{sync::mpsc, time::sleep_until};
 
 
-use crate::dns_record_collector::RealDnsRecordFetcher;
+use crate::fetcher_registry::FetcherRegistry;
 
 use log::{error, info};
 
@@ -24,6 +45,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
     let mut opts = Options::new();
     opts.optopt("c", "config", "Set the config file path", "FILE");
+    opts.optflag("", "dry-run-rewrites", "Print the names of the records before and after the rewrite rules, then exit");
     let matches = opts.parse(std::env::args().skip(1))?;
 
     let config_path = match matches.opt_str("c") {
@@ -36,19 +58,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 
 
-    let config_path_clone = config_path.clone();
-    let config = config::load_config(config_path)?;
-    info!("Config:\n{}", config.to_string());
+    let mut config = config::load_config(config_path.clone())?;
+    info!("Config:\n{}", config);
 
-    let mut source_file_paths: Vec<String> = Vec::new();
-    for source_path in config.source_file_paths.iter() {
-        // validate the source file path
-        if !std::path::Path::new(source_path).exists() {
-            panic!("Source file path does not exist: {}", source_path);
+    if matches.opt_present("dry-run-rewrites") {
+        let collector = dns_record_collector::DnsRecordCollector::new(
+            config.clone(),
+            Box::new(FetcherRegistry::default()),
+        );
+        for preview in collector.preview_rewrites().await {
+            let marker = if preview.before == preview.after { " (unchanged)" } else { "" };
+            println!("{}: {} -> {}{}", preview.source_name, preview.before, preview.after, marker);
         }
-        source_file_paths.push(source_path.clone());
+        return Ok(());
     }
 
+    let mut source_file_paths = config.source_file_paths.clone();
+    let mut watched_paths = paths_to_watch(&config, &config_path)?;
+
 
     // TODO: finish this
     // https://github.com/notify-rs/notify/issues/380
@@ -57,70 +84,161 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Also needs to make the actual file writing loop async
     let (tx, mut rx) = mpsc::channel(100);
     let mut watcher = RecommendedWatcher::new(move |result: std::result::Result<notify::Event, notify::Error>| {
-            tx.blocking_send(result).expect("Failed to send event");
+            // The receiver is only gone when main is shutting down, there is nobody left to tell
+            let _ = tx.blocking_send(result);
         },
         notify::Config::default()
     )?;
 
-    let mut paths = source_file_paths.clone();
-    paths.push(config_path_clone);
-    for path_string in paths {
-        let path = std::path::PathBuf::from(path_string);
-        watcher.watch(&path, RecursiveMode::NonRecursive).unwrap();
+    for path in watched_paths.iter() {
+        watcher.watch(std::path::Path::new(path), RecursiveMode::NonRecursive)?;
     }
 
-    // This is a simple loop, but you may want to use more complex logic here,
-    // for example to handle I/O.
-    while let Some(res) = rx.recv().await {
-        tokio::spawn(async move {println!("got = {:?}", res);});
-    }
-    
-
-    // handle config updates
+    // config updates are handled by the update loop below
     info!("Setup file watcher...");
-    config::handle_config_update(rx).await;
-    
-
 
-    let period_time_in_minutes = Duration::from_secs(config.call_frequency_in_minutes * 60);
     // TODO:  Run the infinite loop in a separate task
+    // The collector lives across cycles, so the fetcher can skip sources that did not change,
+    // and it knows when each source is due to be fetched again
+    let mut collector = dns_record_collector::DnsRecordCollector::new(
+        config.clone(),
+        Box::new(FetcherRegistry::default()),
+    );
+    let mut due_sources = collector.due_sources();
+    let mut source_file_changed = false;
     loop {
-        info!("Restarting CoreDNS update loop...");
-        let mut collector = dns_record_collector::DnsRecordCollector::new(
-            config.clone(),
-            Box::new(RealDnsRecordFetcher),
-        );
-        let record_map = collector.collect_dns_records().await.unwrap();
-        std::mem::drop(collector);
-
-        let result = write_records(record_map, config.temp_storage_path.clone()).await;
-        let additional_source_file_paths: Vec<String> = match result {
-            Ok(paths) => paths,
+        info!("Restarting CoreDNS update loop for {}...", due_sources.join(", "));
+        let collected = match collector.collect_dns_records_of(&due_sources).await {
+            // The source files are part of the destination, a changed one is written even without new records
+            Ok(None) if source_file_changed => collector.render_dns_records().await.map(Some),
+            collected => collected,
+        };
+        source_file_changed = false;
+        match collected {
+            Ok(Some(record_map)) => write_destinations(&config, &source_file_paths, record_map).await,
+            Ok(None) => info!("No DNS records changed, leaving the zone files as they are"),
             Err(e) => {
-                error!("Failed to write DNS records to file: {}", e);
-                continue;
+                // The zone files keep the records of the last cycle that succeeded
+                error!("Failed to collect DNS records, leaving the zone files as they are: {}", e)
             }
-        };
+        }
 
-        // merge the source file paths
-        let mut source_file_paths = source_file_paths.clone();
-        source_file_paths.extend(additional_source_file_paths);
+        // Wait for the next source that is due, every source has its own interval.
+        // Sources that can tell when their records changed, like a lease file, don't wait for their interval.
+        tokio::select! {
+            _ = sleep_until(collector.next_fetch()) => {
+                due_sources = collector.due_sources();
+            }
+            source_name = collector.wait_for_change() => {
+                info!("DNS records of {} may have changed, regenerating", source_name);
+                due_sources = collector.due_sources();
+                if !due_sources.contains(&source_name) {
+                    due_sources.push(source_name);
+                }
+            }
+            Some(_) = rx.recv() => {
+                // The config or a source file changed, a changed config starts over with a new collector
+                match config::load_config(config_path.clone()) {
+                    Ok(new_config) if new_config != config => match paths_to_watch(&new_config, &config_path) {
+                        Ok(new_watched_paths) => {
+                            info!("Config updated:\n{}", new_config);
+                            // Watch the source files of the new config, the config itself stays watched
+                            for path in watched_paths.iter().filter(|path| !new_watched_paths.contains(path)) {
+                                if let Err(e) = watcher.unwatch(std::path::Path::new(path)) {
+                                    error!("Failed to stop watching {}: {}", path, e);
+                                }
+                            }
+                            for path in new_watched_paths.iter().filter(|path| !watched_paths.contains(path)) {
+                                if let Err(e) = watcher.watch(std::path::Path::new(path), RecursiveMode::NonRecursive) {
+                                    error!("Failed to watch {}: {}", path, e);
+                                }
+                            }
+                            watched_paths = new_watched_paths;
+                            config = new_config;
+                            source_file_paths = config.source_file_paths.clone();
+                            collector = dns_record_collector::DnsRecordCollector::new(
+                                config.clone(),
+                                Box::new(FetcherRegistry::default()),
+                            );
+                        }
+                        Err(e) => error!("Failed to reload config {}, keeping the current config: {}", config_path, e),
+                    },
+                    Ok(_) => {
+                        info!("A source file changed, regenerating");
+                        source_file_changed = true;
+                    }
+                    Err(e) => error!("Failed to reload config {}, keeping the current config: {}", config_path, e),
+                }
+                due_sources = collector.due_sources();
+            }
+        }
+    }
+}
 
-        let result = file_writer::merge_source_files(
-            source_file_paths,
-            &config.destination_file_path.clone(),
-        )
-        .await;
-        match result {
+// The files to watch: the source files, the source files of the zones and the config itself.
+// Every source file has to exist, at startup and when the config is reloaded.
+fn paths_to_watch(config: &config::Config, config_path: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut paths: Vec<String> = Vec::new();
+    for source_path in config.source_file_paths.iter() {
+        // validate the source file path
+        if !std::path::Path::new(source_path).exists() {
+            return Err(format!("Source file path does not exist: {}", source_path).into());
+        }
+        paths.push(source_path.clone());
+    }
+    for source_path in config.zones.iter().flat_map(|zone| zone.source_file_paths.iter()) {
+        if !std::path::Path::new(source_path).exists() {
+            return Err(format!("Source file path of zone does not exist: {}", source_path).into());
+        }
+        paths.push(source_path.clone());
+    }
+    paths.push(config_path.to_string());
+    Ok(paths)
+}
+
+// Write the records to the zone files, or merge them with the source files into the destination file without zones
+pub async fn write_destinations(
+    config: &config::Config,
+    source_file_paths: &[String],
+    record_map: HashMap<String, Vec<DnsRecord>>,
+) {
+    // Every zone has its own destination file, without zones everything goes into destination_file_path
+    for zone in &config.zones {
+        match write_zone(config, zone, &record_map).await {
             Ok(_) => {
-                info!("Successfully merged source files")
+                info!("Successfully wrote zone {}", zone.origin)
             }
             Err(e) => {
-                error!("Failed to merge source files: {}", e)
+                error!("Failed to write zone {}: {}", zone.origin, e)
             }
         }
+    }
+    if config.zones.is_empty() {
+        let result = write_records(record_map, config.temp_storage_path.clone()).await;
+        match result {
+            Ok(additional_source_file_paths) => {
+                // merge the source file paths
+                let mut source_file_paths = source_file_paths.to_vec();
+                source_file_paths.extend(additional_source_file_paths);
 
-        sleep(period_time_in_minutes).await;
+                let result = file_writer::merge_source_files(
+                    source_file_paths,
+                    &config.destination_file_path.clone(),
+                )
+                .await;
+                match result {
+                    Ok(_) => {
+                        info!("Successfully merged source files")
+                    }
+                    Err(e) => {
+                        error!("Failed to merge source files: {}", e)
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Failed to write DNS records to file: {}", e);
+            }
+        }
     }
 }
 
@@ -129,6 +247,9 @@ pub async fn write_records(
     temp_storage_path: String,
 ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
     let mut source_file_paths: Vec<String> = Vec::new();
+    // Sorted by source name, so the destination file is the same every time the records are
+    let mut dns_records_by_source: Vec<(String, Vec<DnsRecord>)> = dns_records_by_source.into_iter().collect();
+    dns_records_by_source.sort_by(|a, b| a.0.cmp(&b.0));
     for source in dns_records_by_source {
         let dns_records = source.1;
 
@@ -143,3 +264,27 @@ pub async fn write_records(
     }
     Ok(source_file_paths)
 }
+
+// Write the records of every source in the zone to a file per source, and merge those into the zone file
+pub async fn write_zone(
+    config: &config::Config,
+    zone: &config::ZoneConfig,
+    dns_records_by_source: &HashMap<String, Vec<DnsRecord>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut zone_records_by_source: HashMap<String, Vec<DnsRecord>> = HashMap::new();
+    for (source_name, dns_records) in dns_records_by_source {
+        let zone_records: Vec<DnsRecord> = dns_records
+            .iter()
+            .filter(|record| config.zone_for(&record.fqdn) == Some(zone))
+            .cloned()
+            .collect();
+        if !zone_records.is_empty() {
+            let file_name = format!("{}.{}", source_name, zone.origin.trim_end_matches('.'));
+            zone_records_by_source.insert(file_name, zone_records);
+        }
+    }
+
+    let record_file_paths = write_records(zone_records_by_source, config.temp_storage_path.clone()).await?;
+    file_writer::write_zone_file(zone, config.ttl, record_file_paths).await?;
+    Ok(())
+}
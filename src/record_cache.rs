@@ -0,0 +1,117 @@
+// record_cache.rs
+use crate::dns_record::DnsRecord;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The last successfully fetched DNS records of a source, used when the source fails
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CachedRecords {
+    /// Seconds since the UNIX epoch
+    pub fetched_at: u64,
+    pub records: Vec<DnsRecord>,
+}
+
+impl CachedRecords {
+    pub fn new(records: Vec<DnsRecord>) -> CachedRecords {
+        CachedRecords {
+            fetched_at: now_in_seconds(),
+            records,
+        }
+    }
+
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(now_in_seconds().saturating_sub(self.fetched_at))
+    }
+}
+
+/// The cache file of a source lives next to its temporary records file
+pub fn cache_file_path(temp_storage_path: &str, source_name: &str) -> String {
+    temp_storage_path.to_string() + source_name + ".cache.json"
+}
+
+/// Write the cached records of a source to its cache file
+///
+/// # Arguments
+/// * `temp_storage_path` - A string containing the path of the temporary storage
+/// * `source_name` - A string containing the name of the source
+/// * `cached_records` - The records to cache
+///
+/// # Returns
+/// * `io::Result<()>` - A result indicating success or failure
+///
+pub async fn save(
+    temp_storage_path: &str,
+    source_name: &str,
+    cached_records: &CachedRecords,
+) -> io::Result<()> {
+    let cache_file_path = cache_file_path(temp_storage_path, source_name);
+    let content = serde_json::to_string(cached_records)?;
+    tokio::fs::write(&cache_file_path, content).await?;
+    info!(
+        "Cached {} DNS records of {} in {}",
+        cached_records.records.len(),
+        source_name,
+        cache_file_path
+    );
+    Ok(())
+}
+
+/// Read the cached records of a source from its cache file
+///
+/// # Arguments
+/// * `temp_storage_path` - A string containing the path of the temporary storage
+/// * `source_name` - A string containing the name of the source
+///
+/// # Returns
+/// * `io::Result<CachedRecords>` - The cached records, or an error when there is no (valid) cache file
+///
+pub async fn load(temp_storage_path: &str, source_name: &str) -> io::Result<CachedRecords> {
+    let content =
+        tokio::fs::read_to_string(cache_file_path(temp_storage_path, source_name)).await?;
+    let cached_records = serde_json::from_str(&content)?;
+    Ok(cached_records)
+}
+
+fn now_in_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_save_and_load_cached_records() {
+        let temp_storage_path = "testdata/";
+        let source_name = "test_save_and_load_cached_records";
+        let cached_records = CachedRecords::new(vec![DnsRecord {
+            fqdn: "a.example.com".to_string(),
+            ip: "127.0.0.1".to_string(),
+            cluster_name: "kind".to_string(),
+            ..Default::default()
+        }]);
+
+        save(temp_storage_path, source_name, &cached_records)
+            .await
+            .unwrap();
+        let loaded = load(temp_storage_path, source_name).await.unwrap();
+        assert_eq!(loaded, cached_records);
+        assert!(loaded.age() < Duration::from_secs(5));
+
+        // Clean up
+        tokio::fs::remove_file(cache_file_path(temp_storage_path, source_name))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_load_missing_cache_fails() {
+        let result = load("testdata/", "test_load_missing_cache_fails").await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,171 @@
+// record_filter.rs
+use crate::config::{FilterRule, RecordFilters};
+use crate::dns_record::DnsRecord;
+use regex::Regex;
+use std::error::Error;
+
+/// The compiled filters of a source, deciding which of its records are used
+pub struct RecordFilter {
+    include: Vec<CompiledRule>,
+    exclude: Vec<CompiledRule>,
+}
+
+// The patterns of a rule per field, an empty list matches anything
+struct CompiledRule {
+    namespace: Vec<Regex>,
+    kind: Vec<Regex>,
+    controller: Vec<Regex>,
+    cluster_name: Vec<Regex>,
+    fqdn: Vec<Regex>,
+}
+
+impl RecordFilter {
+    /// Compile the filters of a source
+    ///
+    /// # Arguments
+    /// * `filters` - The include and exclude rules of the source
+    ///
+    /// # Returns
+    /// * `Result<RecordFilter, Box<dyn Error>>` - The filter, or an error when a regular expression is invalid
+    ///
+    pub fn new(filters: &RecordFilters) -> Result<RecordFilter, Box<dyn Error>> {
+        Ok(RecordFilter {
+            include: compile_rules(&filters.include)?,
+            exclude: compile_rules(&filters.exclude)?,
+        })
+    }
+
+    /// Whether the record is used, it matches an include rule, or there are none, and no exclude rule
+    pub fn matches(&self, record: &DnsRecord) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|rule| rule.matches(record)))
+            && !self.exclude.iter().any(|rule| rule.matches(record))
+    }
+}
+
+impl CompiledRule {
+    fn matches(&self, record: &DnsRecord) -> bool {
+        let field_matches = |patterns: &[Regex], value: &str| {
+            patterns.is_empty() || patterns.iter().any(|p| p.is_match(value))
+        };
+        field_matches(&self.namespace, &record.namespace)
+            && field_matches(&self.kind, &record.kind)
+            && field_matches(&self.controller, &record.controller)
+            && field_matches(&self.cluster_name, &record.cluster_name)
+            && field_matches(&self.fqdn, &record.fqdn)
+    }
+}
+
+fn compile_rules(rules: &[FilterRule]) -> Result<Vec<CompiledRule>, Box<dyn Error>> {
+    rules
+        .iter()
+        .map(|rule| {
+            Ok(CompiledRule {
+                namespace: compile_patterns(&rule.namespace)?,
+                kind: compile_patterns(&rule.kind)?,
+                controller: compile_patterns(&rule.controller)?,
+                cluster_name: compile_patterns(&rule.cluster_name)?,
+                fqdn: compile_patterns(&rule.fqdn)?,
+            })
+        })
+        .collect()
+}
+
+fn compile_patterns(patterns: &[String]) -> Result<Vec<Regex>, Box<dyn Error>> {
+    patterns
+        .iter()
+        .map(|pattern| compile_pattern(pattern))
+        .collect()
+}
+
+// A regular expression between slashes is used as is, a glob must match the whole value
+pub fn compile_pattern(pattern: &str) -> Result<Regex, Box<dyn Error>> {
+    if pattern.len() > 1 && pattern.starts_with('/') && pattern.ends_with('/') {
+        let expression = &pattern[1..pattern.len() - 1];
+        return Regex::new(expression)
+            .map_err(|e| format!("invalid regular expression {} - {}", pattern, e).into());
+    }
+    let mut expression = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => expression.push_str(".*"),
+            '?' => expression.push('.'),
+            c => expression.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    expression.push('$');
+    Ok(Regex::new(&expression)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(namespace: &str, kind: &str, fqdn: &str) -> DnsRecord {
+        DnsRecord {
+            namespace: namespace.to_string(),
+            kind: kind.to_string(),
+            fqdn: fqdn.to_string(),
+            controller: "istio".to_string(),
+            cluster_name: "kind".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_exclude_with_glob() {
+        let filter = RecordFilter::new(&RecordFilters {
+            exclude: vec![FilterRule {
+                namespace: patterns(&["test-*", "sandbox"]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert!(filter.matches(&record("default", "Service", "app.example.com")));
+        assert!(!filter.matches(&record("test-42", "Service", "app.example.com")));
+        assert!(!filter.matches(&record("sandbox", "Service", "app.example.com")));
+        assert!(filter.matches(&record("sandbox-2", "Service", "app.example.com")));
+    }
+
+    #[test]
+    fn test_include_with_regex_and_all_fields_of_a_rule() {
+        let filter = RecordFilter::new(&RecordFilters {
+            include: vec![
+                FilterRule {
+                    kind: patterns(&["Ingress"]),
+                    fqdn: patterns(&[r"/\.example\.com$/"]),
+                    ..Default::default()
+                },
+                FilterRule {
+                    controller: patterns(&["/^(istio|nginx)$/"]),
+                    namespace: patterns(&["gateways"]),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert!(filter.matches(&record("default", "Ingress", "app.example.com")));
+        assert!(!filter.matches(&record("default", "Ingress", "app.example.org")));
+        assert!(!filter.matches(&record("default", "Service", "app.example.com")));
+        assert!(filter.matches(&record("gateways", "Service", "app.example.org")));
+    }
+
+    #[test]
+    fn test_invalid_regex_fails() {
+        let filters = RecordFilters {
+            include: vec![FilterRule {
+                fqdn: patterns(&["/(unclosed/"]),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(RecordFilter::new(&filters).is_err());
+    }
+}
@@ -0,0 +1,569 @@
+// record_stream.rs
+use crate::auth;
+use crate::config::ExternalSource;
+use crate::dns_record::DnsRecord;
+use crate::dns_record_collector::DnsRecordFetcher;
+use crate::http_client;
+use log::{info, warn};
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio::task::AbortHandle;
+use tokio::time::{sleep, timeout, Duration};
+
+/// The records of a source, kept up to date by a long-lived stream of changes instead of polling.
+/// The stream runs in the background, every change wakes up `wait_for_change`.
+/// When the stream drops the records are forgotten, so the source is polled until the stream is started again.
+/// Dropping the RecordStream ends the stream, like when the config is reloaded.
+#[derive(Default)]
+pub struct RecordStream {
+    shared: Arc<SharedStream>,
+    task: Mutex<Option<AbortHandle>>,
+}
+
+// The part of the stream the background task shares, so the task doesn't keep the RecordStream alive
+#[derive(Default)]
+struct SharedStream {
+    state: Mutex<StreamState>,
+    changed: Notify,
+}
+
+#[derive(Default)]
+struct StreamState {
+    running: bool,
+    // The records by their key, None until the stream is connected and the records are loaded
+    records: Option<BTreeMap<String, DnsRecord>>,
+    // Whether the records changed since they were last read, so a wait that is cancelled doesn't lose the change
+    changed: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "UPPERCASE")]
+enum EventType {
+    Added,
+    Modified,
+    Deleted,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+struct StreamEvent {
+    #[serde(rename = "type")]
+    event_type: EventType,
+    record: DnsRecord,
+}
+
+impl RecordStream {
+    /// The current records of the stream, None when the stream is not connected
+    pub fn records(&self) -> Option<Vec<DnsRecord>> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.changed = false;
+        state
+            .records
+            .as_ref()
+            .map(|records| records.values().cloned().collect())
+    }
+
+    /// Start streaming the changes of the source in the background, unless the stream is already running
+    ///
+    /// # Arguments
+    /// * `source` - The source with the stream config
+    /// * `ca_cert_base64` - A string containing the base64 encoded PEM of the global CA certificate(s), may be empty
+    /// * `snapshot_fetcher` - Fetches all records of the source, once the stream is connected
+    ///
+    pub fn start(
+        &self,
+        source: &ExternalSource,
+        ca_cert_base64: &str,
+        snapshot_fetcher: Box<dyn DnsRecordFetcher>,
+    ) {
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            if state.running {
+                return;
+            }
+            state.running = true;
+        }
+        let record_stream = self.shared.clone();
+        let source = source.clone();
+        let ca_cert_base64 = ca_cert_base64.to_string();
+        let task = tokio::spawn(async move {
+            let result = record_stream
+                .run(&source, &ca_cert_base64, snapshot_fetcher.as_ref())
+                .await;
+            let was_connected = {
+                let mut state = record_stream.state.lock().unwrap();
+                state.running = false;
+                let was_connected = state.records.take().is_some();
+                state.changed |= was_connected;
+                was_connected
+            };
+            match result {
+                Ok(()) => info!("The stream of {} ended", source.source_name),
+                Err(e) => warn!("The stream of {} failed - {}", source.source_name, e),
+            }
+            // The streamed records may be stale by now, poll the source right away
+            if was_connected {
+                record_stream.changed.notify_one();
+            }
+        });
+        *self.task.lock().unwrap() = Some(task.abort_handle());
+    }
+
+    /// Wait until the records changed since they were last read, and then until no more changes arrive within the debounce time
+    pub async fn wait_for_change(&self, debounce: Duration) {
+        let shared = &self.shared;
+        loop {
+            let notified = shared.changed.notified();
+            if shared.state.lock().unwrap().changed {
+                break;
+            }
+            notified.await;
+        }
+        loop {
+            tokio::select! {
+                _ = sleep(debounce) => return,
+                _ = shared.changed.notified() => {}
+            }
+        }
+    }
+}
+
+impl Drop for RecordStream {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.lock().unwrap().take() {
+            task.abort();
+        }
+    }
+}
+
+impl SharedStream {
+    async fn run(
+        &self,
+        source: &ExternalSource,
+        ca_cert_base64: &str,
+        snapshot_fetcher: &dyn DnsRecordFetcher,
+    ) -> Result<(), String> {
+        let stream = source
+            .stream
+            .as_ref()
+            .ok_or("the source has no stream configured")?;
+        let client =
+            http_client::build_client(source, ca_cert_base64).map_err(|e| e.to_string())?;
+        let mut request = client
+            .get(&stream.url)
+            .header(ACCEPT, "text/event-stream, application/x-ndjson");
+        if let Some(auth) = &source.auth {
+            request = auth::apply(request, auth).map_err(|e| e.to_string())?;
+        }
+        let mut response = request
+            .send()
+            .await
+            .map_err(|e| format!("failed to connect to {} - {}", stream.url, e))?;
+        if !response.status().is_success() {
+            return Err(format!(
+                "{} responded with {}",
+                stream.url,
+                response.status()
+            ));
+        }
+        let server_sent_events = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("text/event-stream"));
+
+        // Events that arrive while the records are loaded are applied after, in order,
+        // so no change is missed between loading the records and reading the stream.
+        // The records are polled, without starting another stream.
+        let polled_source = ExternalSource {
+            stream: None,
+            ..source.clone()
+        };
+        let snapshot = snapshot_fetcher
+            .fetch_dns_records(&polled_source, ca_cert_base64)
+            .await
+            .map_err(|e| format!("failed to load the records - {}", e))?;
+        {
+            let mut state = self.state.lock().unwrap();
+            state.records = Some(snapshot.into_iter().map(|r| (key(&r), r)).collect());
+            state.changed = true;
+        }
+        info!(
+            "Streaming the changes of {} from {}",
+            source.source_name, stream.url
+        );
+        self.changed.notify_one();
+
+        // A half-open connection never ends, the server must send something, like a keep-alive, within the idle timeout
+        let idle_timeout = Duration::from_secs(stream.idle_timeout_in_seconds);
+        let mut parser = EventParser::new(server_sent_events);
+        loop {
+            let chunk = timeout(idle_timeout, response.chunk())
+                .await
+                .map_err(|_| {
+                    format!(
+                        "nothing received from {} within {} seconds",
+                        stream.url, stream.idle_timeout_in_seconds
+                    )
+                })?
+                .map_err(|e| format!("failed to read from {} - {}", stream.url, e))?;
+            let Some(chunk) = chunk else {
+                break;
+            };
+            let events = parser.push(&chunk);
+            if events.is_empty() {
+                continue;
+            }
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(records) = state.records.as_mut() {
+                    for event in events {
+                        apply(records, event);
+                    }
+                }
+                state.changed = true;
+            }
+            self.changed.notify_one();
+        }
+        Ok(())
+    }
+}
+
+// Identifies a record across events, a MODIFIED event replaces the record with the same key.
+// The address is part of the key, like in the export a name may have a record per address,
+// so a changed address is a DELETED event of the old record and an ADDED event of the new one.
+fn key(record: &DnsRecord) -> String {
+    format!(
+        "{}/{}/{}/{}/{}",
+        record.kind, record.namespace, record.fqdn, record.record_type, record.ip
+    )
+}
+
+fn apply(records: &mut BTreeMap<String, DnsRecord>, event: StreamEvent) {
+    match event.event_type {
+        EventType::Added | EventType::Modified => {
+            records.insert(key(&event.record), event.record);
+        }
+        EventType::Deleted => {
+            records.remove(&key(&event.record));
+        }
+    }
+}
+
+// Splits the chunks of the stream into events, either Server-Sent Events or one JSON event per line
+struct EventParser {
+    server_sent_events: bool,
+    buffer: Vec<u8>,
+    // The data lines of the Server-Sent Event being read
+    data: Vec<String>,
+}
+
+impl EventParser {
+    fn new(server_sent_events: bool) -> EventParser {
+        EventParser {
+            server_sent_events,
+            buffer: Vec::new(),
+            data: Vec::new(),
+        }
+    }
+
+    // The events completed by the chunk, invalid events are skipped
+    fn push(&mut self, chunk: &[u8]) -> Vec<StreamEvent> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(newline) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=newline).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+
+            let payload = if self.server_sent_events {
+                if line.is_empty() && !self.data.is_empty() {
+                    Some(self.data.drain(..).collect::<Vec<_>>().join("\n"))
+                } else {
+                    // Comments, like keep-alives, event names, ids and retry times are not used
+                    if let Some(data) = line.strip_prefix("data:") {
+                        self.data
+                            .push(data.strip_prefix(' ').unwrap_or(data).to_string());
+                    }
+                    None
+                }
+            } else if line.trim().is_empty() {
+                None
+            } else {
+                Some(line.to_string())
+            };
+
+            if let Some(payload) = payload {
+                match serde_json::from_str(&payload) {
+                    Ok(event) => events.push(event),
+                    Err(e) => warn!("Skipping invalid stream event {} - {}", payload, e),
+                }
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StreamConfig;
+    use crate::idec_fetcher::IdecDnsRecordFetcher;
+    use crate::test_support::{TestResponse, TestServer};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+
+    fn record(fqdn: &str, ip: &str) -> String {
+        format!(
+            r#"{{"clusterIP": "", "clusterName": "kind", "controller": "nginx", "fqdn": "{}", "ip": "{}", "kind": "Ingress", "namespace": "default", "port": "80"}}"#,
+            fqdn, ip
+        )
+    }
+
+    fn event(event_type: &str, fqdn: &str, ip: &str) -> String {
+        format!(
+            r#"{{"type": "{}", "record": {}}}"#,
+            event_type,
+            record(fqdn, ip)
+        )
+    }
+
+    #[test]
+    fn test_parse_server_sent_events_split_over_chunks() {
+        let mut parser = EventParser::new(true);
+        let stream = format!(
+            ": keep-alive\n\nevent: change\ndata: {}\n\ndata: {}\r\n\r\ndata: not json\n\n",
+            event("ADDED", "a.example.com", "10.0.0.1"),
+            event("DELETED", "b.example.com", "10.0.0.2")
+        );
+        let (first, second) = stream.as_bytes().split_at(40);
+
+        let mut events = parser.push(first);
+        events.extend(parser.push(second));
+
+        let summary: Vec<(EventType, &str)> = events
+            .iter()
+            .map(|e| (e.event_type, e.record.fqdn.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (EventType::Added, "a.example.com"),
+                (EventType::Deleted, "b.example.com")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_json_lines() {
+        let mut parser = EventParser::new(false);
+        let stream = format!(
+            "{}\n\n{}\n{}",
+            event("ADDED", "a.example.com", "10.0.0.1"),
+            event("MODIFIED", "a.example.com", "10.0.0.3"),
+            event("DELETED", "a.example.com", "10.0.0.3")
+        );
+
+        let events = parser.push(stream.as_bytes());
+
+        // The last line is not complete yet
+        let summary: Vec<(EventType, &str)> = events
+            .iter()
+            .map(|e| (e.event_type, e.record.ip.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (EventType::Added, "10.0.0.1"),
+                (EventType::Modified, "10.0.0.3")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_change_is_kept_when_the_wait_is_cancelled() {
+        let record_stream = RecordStream::default();
+        record_stream.shared.state.lock().unwrap().changed = true;
+        record_stream.shared.changed.notify_one();
+
+        // Cancelled during the debounce, like when another source is due first
+        let waited = tokio::time::timeout(
+            Duration::from_millis(100),
+            record_stream.wait_for_change(Duration::from_secs(1)),
+        )
+        .await;
+        assert!(waited.is_err());
+
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            record_stream.wait_for_change(Duration::from_millis(100)),
+        )
+        .await
+        .expect("The change was lost");
+
+        // Reading the records takes the change
+        record_stream.records();
+        let waited = tokio::time::timeout(
+            Duration::from_millis(300),
+            record_stream.wait_for_change(Duration::from_millis(100)),
+        )
+        .await;
+        assert!(waited.is_err());
+    }
+
+    // A stream that sends the events and stays open until the sender is dropped
+    async fn start_stream_server(events: Vec<String>) -> (String, oneshot::Sender<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/stream", listener.local_addr().unwrap());
+        let (close, closed) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut body = String::new();
+            for event in events {
+                body.push_str(&format!("data: {}\n\n", event));
+            }
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n{}",
+                body
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            let _ = closed.await;
+        });
+        (url, close)
+    }
+
+    #[tokio::test]
+    async fn test_idec_source_follows_stream_and_polls_when_it_drops() {
+        // A name with a record per address, like an ingress with two load balancer addresses
+        let export = format!(
+            "[{}, {}, {}]",
+            record("app.example.com", "10.0.0.1"),
+            record("app.example.com", "10.0.0.5"),
+            record("old.example.com", "10.0.0.2")
+        );
+        let export_server = TestServer::start(move |_| TestResponse::json(&export)).await;
+        let (stream_url, close_stream) = start_stream_server(vec![
+            event("DELETED", "app.example.com", "10.0.0.1"),
+            event("ADDED", "app.example.com", "10.0.0.3"),
+            event("ADDED", "new.example.com", "10.0.0.4"),
+            event("DELETED", "old.example.com", "10.0.0.2"),
+        ])
+        .await;
+        let source = ExternalSource {
+            url: export_server.url.clone(),
+            source_name: "idec".to_string(),
+            stream: Some(StreamConfig {
+                url: stream_url,
+                debounce_in_seconds: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let fetcher = IdecDnsRecordFetcher::new();
+        let fqdns_and_ips = |dns_records: Vec<DnsRecord>| -> Vec<(String, String)> {
+            dns_records.into_iter().map(|r| (r.fqdn, r.ip)).collect()
+        };
+
+        // The stream isn't connected yet, the records are polled
+        let dns_records = fetcher.fetch_dns_records(&source, "").await.unwrap();
+        assert_eq!(dns_records.len(), 3);
+
+        tokio::time::timeout(Duration::from_secs(5), fetcher.wait_for_change(&source))
+            .await
+            .expect("The events of the stream were not noticed");
+        let dns_records = fetcher.fetch_dns_records(&source, "").await.unwrap();
+        assert_eq!(
+            fqdns_and_ips(dns_records),
+            vec![
+                ("app.example.com".to_string(), "10.0.0.3".to_string()),
+                ("app.example.com".to_string(), "10.0.0.5".to_string()),
+                ("new.example.com".to_string(), "10.0.0.4".to_string()),
+            ]
+        );
+        let polls = export_server.requests().len();
+
+        drop(close_stream);
+        tokio::time::timeout(Duration::from_secs(5), fetcher.wait_for_change(&source))
+            .await
+            .expect("The end of the stream was not noticed");
+        let dns_records = fetcher.fetch_dns_records(&source, "").await.unwrap();
+        assert_eq!(dns_records.len(), 3);
+        assert_eq!(export_server.requests().len(), polls + 1);
+    }
+
+    #[tokio::test]
+    async fn test_dropping_the_fetcher_ends_the_stream() {
+        let export = format!("[{}]", record("app.example.com", "10.0.0.1"));
+        let export_server = TestServer::start(move |_| TestResponse::json(&export)).await;
+        // A stream that reports when the client closes the connection
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream_url = format!("http://{}/stream", listener.local_addr().unwrap());
+        let (disconnect, disconnected) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n")
+                .await
+                .unwrap();
+            let mut buffer = [0u8; 1024];
+            while !matches!(stream.read(&mut buffer).await, Ok(0) | Err(_)) {}
+            let _ = disconnect.send(());
+        });
+        let source = ExternalSource {
+            url: export_server.url.clone(),
+            source_name: "idec".to_string(),
+            stream: Some(StreamConfig {
+                url: stream_url,
+                debounce_in_seconds: 0,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let fetcher = IdecDnsRecordFetcher::new();
+        fetcher.fetch_dns_records(&source, "").await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), fetcher.wait_for_change(&source))
+            .await
+            .expect("The stream did not connect");
+
+        // Like the collector of the old config after a reload
+        drop(fetcher);
+        tokio::time::timeout(Duration::from_secs(5), disconnected)
+            .await
+            .expect("The stream was not closed")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_silent_stream_is_dropped_after_idle_timeout() {
+        let export = format!("[{}]", record("app.example.com", "10.0.0.1"));
+        let export_server = TestServer::start(move |_| TestResponse::json(&export)).await;
+        // The stream stays open without sending anything, like a half-open connection
+        let (stream_url, _close_stream) = start_stream_server(vec![]).await;
+        let source = ExternalSource {
+            url: export_server.url.clone(),
+            source_name: "idec".to_string(),
+            stream: Some(StreamConfig {
+                url: stream_url,
+                debounce_in_seconds: 0,
+                idle_timeout_in_seconds: 1,
+            }),
+            ..Default::default()
+        };
+        let fetcher = IdecDnsRecordFetcher::new();
+        fetcher.fetch_dns_records(&source, "").await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), fetcher.wait_for_change(&source))
+            .await
+            .expect("The stream did not connect");
+        fetcher.fetch_dns_records(&source, "").await.unwrap();
+        let polls = export_server.requests().len();
+
+        tokio::time::timeout(Duration::from_secs(5), fetcher.wait_for_change(&source))
+            .await
+            .expect("The silent stream was not dropped");
+        fetcher.fetch_dns_records(&source, "").await.unwrap();
+        assert_eq!(export_server.requests().len(), polls + 1);
+    }
+}
@@ -0,0 +1,201 @@
+// retry.rs
+use crate::config::RetryPolicy;
+use rand::Rng;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Response, StatusCode};
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+// Errors raised while fetching the DNS records of an external source,
+// which carry enough information to decide whether a retry makes sense.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FetchError {
+    // The source answered with a non-success HTTP status
+    Status {
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    },
+    // The source did not answer within its timeout
+    Timeout(Duration),
+    // The update cycle ran out of time before the source answered
+    CycleDeadline,
+}
+
+impl FetchError {
+    pub fn from_response(response: &Response) -> FetchError {
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        FetchError::Status {
+            status: response.status(),
+            retry_after,
+        }
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FetchError::Status { status, .. } => write!(f, "unexpected response status {}", status),
+            FetchError::Timeout(timeout) => write!(f, "timed out after {}s", timeout.as_secs()),
+            FetchError::CycleDeadline => {
+                write!(f, "cycle deadline reached before the fetch completed")
+            }
+        }
+    }
+}
+
+impl Error for FetchError {}
+
+// Whether the error is worth a retry: connection failures, timeouts, 408, 429 and 5xx responses are.
+// Other 4xx responses and JSON decode failures are not, as they will fail the same way again.
+pub fn is_transient(error: &(dyn Error + 'static)) -> bool {
+    if let Some(fetch_error) = error.downcast_ref::<FetchError>() {
+        return match fetch_error {
+            FetchError::Status { status, .. } => {
+                status.is_server_error()
+                    || *status == StatusCode::TOO_MANY_REQUESTS
+                    || *status == StatusCode::REQUEST_TIMEOUT
+            }
+            FetchError::Timeout(_) => true,
+            FetchError::CycleDeadline => false,
+        };
+    }
+    if let Some(reqwest_error) = error.downcast_ref::<reqwest::Error>() {
+        if reqwest_error.is_decode() || reqwest_error.is_builder() {
+            return false;
+        }
+        if let Some(status) = reqwest_error.status() {
+            return status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS;
+        }
+        return reqwest_error.is_connect()
+            || reqwest_error.is_timeout()
+            || reqwest_error.is_request()
+            || reqwest_error.is_body();
+    }
+    false
+}
+
+// The delay the source asked for with a `Retry-After` header on a 429 or 503 response
+pub fn retry_after(error: &(dyn Error + 'static)) -> Option<Duration> {
+    match error.downcast_ref::<FetchError>() {
+        Some(FetchError::Status {
+            status,
+            retry_after,
+        }) if *status == StatusCode::TOO_MANY_REQUESTS
+            || *status == StatusCode::SERVICE_UNAVAILABLE =>
+        {
+            *retry_after
+        }
+        _ => None,
+    }
+}
+
+// Exponential backoff for the given (1-based) failed attempt, capped at the max delay of the policy
+pub fn backoff_delay(policy: &RetryPolicy, attempt: u32) -> Duration {
+    let exponent = attempt.saturating_sub(1).min(31);
+    let delay_in_millis = policy
+        .base_delay_in_millis
+        .saturating_mul(1u64 << exponent)
+        .min(policy.max_delay_in_millis);
+
+    if policy.jitter && delay_in_millis > 1 {
+        let jittered = rand::thread_rng().gen_range(delay_in_millis / 2..=delay_in_millis);
+        Duration::from_millis(jittered)
+    } else {
+        Duration::from_millis(delay_in_millis)
+    }
+}
+
+// Retry-After is either a number of seconds, or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value.trim()).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(jitter: bool) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay_in_millis: 100,
+            max_delay_in_millis: 1000,
+            jitter,
+        }
+    }
+
+    #[test]
+    fn test_backoff_delay_grows_exponentially_up_to_max_delay() {
+        let policy = policy(false);
+        assert_eq!(backoff_delay(&policy, 1), Duration::from_millis(100));
+        assert_eq!(backoff_delay(&policy, 2), Duration::from_millis(200));
+        assert_eq!(backoff_delay(&policy, 3), Duration::from_millis(400));
+        assert_eq!(backoff_delay(&policy, 5), Duration::from_millis(1000));
+        assert_eq!(backoff_delay(&policy, 64), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_backoff_delay_with_jitter_stays_within_bounds() {
+        let policy = policy(true);
+        for _ in 0..100 {
+            let delay = backoff_delay(&policy, 3);
+            assert!(delay >= Duration::from_millis(200) && delay <= Duration::from_millis(400));
+        }
+    }
+
+    #[test]
+    fn test_is_transient_by_status() {
+        let status_error = |status: StatusCode| FetchError::Status {
+            status,
+            retry_after: None,
+        };
+        assert!(is_transient(&status_error(StatusCode::SERVICE_UNAVAILABLE)));
+        assert!(is_transient(&status_error(StatusCode::TOO_MANY_REQUESTS)));
+        assert!(!is_transient(&status_error(StatusCode::NOT_FOUND)));
+        assert!(!is_transient(&status_error(StatusCode::UNAUTHORIZED)));
+        assert!(is_transient(&FetchError::Timeout(Duration::from_secs(1))));
+        assert!(!is_transient(&FetchError::CycleDeadline));
+    }
+
+    #[test]
+    fn test_json_decode_errors_are_permanent() {
+        let error = serde_json::from_str::<Vec<String>>("not json").unwrap_err();
+        assert!(!is_transient(&error));
+    }
+
+    #[test]
+    fn test_retry_after_only_for_429_and_503() {
+        let error = FetchError::Status {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            retry_after: Some(Duration::from_secs(7)),
+        };
+        assert_eq!(retry_after(&error), Some(Duration::from_secs(7)));
+
+        let error = FetchError::Status {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            retry_after: Some(Duration::from_secs(7)),
+        };
+        assert_eq!(retry_after(&error), None);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
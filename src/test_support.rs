@@ -0,0 +1,199 @@
+// test_support.rs
+// A minimal HTTP/1.1 server, standing in for IDEC and other HTTP APIs in tests,
+// and the fixtures the tests of the fetchers share
+use crate::config::{ExternalSource, SourceKind};
+use crate::dns_record::DnsRecord;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, UnixListener};
+
+#[derive(Debug, Clone, Default)]
+pub struct TestRequest {
+    pub method: String,
+    // The path including the query string
+    pub path: String,
+    // Header names are lower case
+    pub headers: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl TestResponse {
+    pub fn json(body: &str) -> TestResponse {
+        TestResponse {
+            status: 200,
+            headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+            body: body.to_string(),
+        }
+    }
+
+    pub fn status(status: u16) -> TestResponse {
+        TestResponse {
+            status,
+            headers: vec![],
+            body: "".to_string(),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> TestResponse {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+type Handler = Arc<dyn Fn(&TestRequest) -> TestResponse + Send + Sync>;
+
+pub struct TestServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<TestRequest>>>,
+}
+
+impl TestServer {
+    /// Start a server on a random local port, answering every request with the handler
+    pub async fn start<F>(handler: F) -> TestServer
+    where
+        F: Fn(&TestRequest) -> TestResponse + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Handler = Arc::new(handler);
+
+        let server_requests = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = handler.clone();
+                let requests = server_requests.clone();
+                tokio::spawn(async move {
+                    handle_connection(stream, handler, requests).await;
+                });
+            }
+        });
+
+        TestServer { url, requests }
+    }
+
+    /// Start a server on a unix socket, like the Docker Engine API, answering every request with the handler.
+    /// The url is `unix://<path>`, an existing socket file at the path is replaced.
+    pub async fn start_unix<F>(path: &str, handler: F) -> TestServer
+    where
+        F: Fn(&TestRequest) -> TestResponse + Send + Sync + 'static,
+    {
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path).unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Handler = Arc::new(handler);
+
+        let server_requests = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = handler.clone();
+                let requests = server_requests.clone();
+                tokio::spawn(async move {
+                    handle_connection(stream, handler, requests).await;
+                });
+            }
+        });
+
+        TestServer {
+            url: format!("unix://{}", path),
+            requests,
+        }
+    }
+
+    pub fn requests(&self) -> Vec<TestRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    handler: Handler,
+    requests: Arc<Mutex<Vec<TestRequest>>>,
+) {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    while !buffer.windows(4).any(|window| window == b"\r\n\r\n") {
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+        }
+    }
+
+    let head = String::from_utf8_lossy(&buffer).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+    let mut request = TestRequest {
+        method: request_line.next().unwrap_or("").to_string(),
+        path: request_line.next().unwrap_or("").to_string(),
+        ..Default::default()
+    };
+    for line in lines.take_while(|line| !line.is_empty()) {
+        if let Some((name, value)) = line.split_once(':') {
+            request
+                .headers
+                .insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+    requests.lock().unwrap().push(request.clone());
+
+    let response = handler(&request);
+    let mut raw_response = format!("HTTP/1.1 {} Test\r\n", response.status);
+    for (name, value) in &response.headers {
+        raw_response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    raw_response.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.body.len(),
+        response.body
+    ));
+    let _ = stream.write_all(raw_response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// An external source of the kind, named source_name, with its records in domain_name
+pub fn test_source(
+    source_name: &str,
+    domain_name: &str,
+    url: &str,
+    kind: SourceKind,
+) -> ExternalSource {
+    ExternalSource {
+        kind,
+        url: url.to_string(),
+        source_name: source_name.to_string(),
+        domain_name: domain_name.to_string(),
+        ..Default::default()
+    }
+}
+
+/// A line per record with its FQDN, type and address, followed by the other fields that are set,
+/// so a test compares all the records of a fetch at once
+pub fn record_lines(dns_records: &[DnsRecord]) -> Vec<String> {
+    dns_records
+        .iter()
+        .map(|record| {
+            let mut line = format!("{} {} {}", record.fqdn, record.record_type, record.ip);
+            for (name, value) in [
+                ("kind", &record.kind),
+                ("namespace", &record.namespace),
+                ("controller", &record.controller),
+                ("port", &record.port),
+            ] {
+                if !value.is_empty() {
+                    line.push_str(&format!(" {}={}", name, value));
+                }
+            }
+            if let Some(ttl) = record.ttl {
+                line.push_str(&format!(" ttl={}", ttl));
+            }
+            line
+        })
+        .collect()
+}